@@ -0,0 +1,21 @@
+"""Stand-in webhook receiver for testing alert rules locally.
+
+Prints the payload of every POST request and answers with 204.
+"""
+
+import json
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+
+class Handler(BaseHTTPRequestHandler):
+    def do_POST(self):
+        length = int(self.headers.get("content-length", 0))
+        payload = json.loads(self.rfile.read(length) or b"null")
+        print(json.dumps(payload, indent=2, ensure_ascii=False), flush=True)
+        self.send_response(204)
+        self.end_headers()
+
+
+port = int(sys.argv[1]) if len(sys.argv) > 1 else 8000
+HTTPServer(("127.0.0.1", port), Handler).serve_forever()
@@ -0,0 +1,296 @@
+use crate::collectors::common::wind_direction_degrees;
+use crate::measurements::{unit_for_key, Measurements};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use spin_sdk::http::{Method, Request, Response};
+use spin_sdk::key_value::Store;
+use std::cell::RefCell;
+
+const STORE_NAME: &str = "alerts";
+const RULE_PREFIX: &str = "rule:";
+const STATE_PREFIX: &str = "state:";
+
+// webhooks are posted by their own component, the only one allowed to reach any host
+const DELIVERY_URL: &str = "http://weather-data-aggregator-webhooks.spin.internal/";
+
+const DEFAULT_COOLDOWN_MINUTES: u64 = 60;
+const MAX_RULE_NAME_LENGTH: usize = 64;
+
+// keys that cannot be compared with a threshold
+const NON_NUMERIC_FIELDS: &[&str] = &["update_time", "wind_direction", "status"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/// Condition on a single measurement, `hysteresis` applies while the rule is active.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Condition {
+    Threshold {
+        field: String,
+        op: Operator,
+        value: f64,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// Wind direction within the sector going clockwise `from` -> `to`, e.g. `["W", "N"]`
+    Sector {
+        sector: (String, String),
+        #[serde(default)]
+        hysteresis: f64,
+    },
+}
+
+impl Condition {
+    /// Whether the condition holds, `None` when the measurement is not available.
+    fn holds(&self, measurements: &Value, active: bool) -> Option<bool> {
+        match self {
+            Condition::Threshold {
+                field,
+                op,
+                value,
+                hysteresis,
+            } => {
+                let reading = measurements.get(field)?.as_f64()?;
+                let margin = if active { *hysteresis } else { 0.0 };
+                let holds = match op {
+                    Operator::Gt => reading > value - margin,
+                    Operator::Ge => reading >= value - margin,
+                    Operator::Lt => reading < value + margin,
+                    Operator::Le => reading <= value + margin,
+                };
+                Some(holds)
+            }
+            Condition::Sector { sector, hysteresis } => {
+                let direction = measurements.get("wind_direction")?.as_str()?;
+                let degrees = wind_direction_degrees(direction)?;
+                let from = wind_direction_degrees(&sector.0)?;
+                let to = wind_direction_degrees(&sector.1)?;
+                let margin = if active { *hysteresis } else { 0.0 };
+                let span = (to - from).rem_euclid(360.0) + 2.0 * margin;
+                let offset = (degrees - from + margin).rem_euclid(360.0);
+                Some(span >= 360.0 || offset <= span)
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Condition::Threshold {
+                field, hysteresis, ..
+            } => {
+                if unit_for_key(field).is_none() || NON_NUMERIC_FIELDS.contains(&field.as_str()) {
+                    return Err(format!("Invalid threshold field: {}", field));
+                }
+                if *hysteresis < 0.0 {
+                    return Err("Hysteresis cannot be negative".to_owned());
+                }
+            }
+            Condition::Sector { sector, hysteresis } => {
+                for name in [&sector.0, &sector.1] {
+                    if wind_direction_degrees(name).is_none() {
+                        return Err(format!("Invalid wind direction: {}", name));
+                    }
+                }
+                if *hysteresis < 0.0 {
+                    return Err("Hysteresis cannot be negative".to_owned());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_cooldown() -> u64 {
+    DEFAULT_COOLDOWN_MINUTES
+}
+
+/// Alert rule, stored under `rule:<name>` in the `alerts` store.
+///
+/// The rule fires when all of the conditions start to hold.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// URL of the station
+    pub station: String,
+    pub conditions: Vec<Condition>,
+    pub webhook: String,
+    #[serde(default = "default_cooldown")]
+    pub cooldown_minutes: u64,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.conditions.is_empty() {
+            return Err("No conditions given".to_owned());
+        }
+        for condition in self.conditions.iter() {
+            condition.validate()?;
+        }
+
+        let webhook =
+            url::Url::parse(&self.webhook).map_err(|e| format!("Invalid webhook: {}", e))?;
+        // plain HTTP is accepted only for receivers running locally
+        let local = matches!(webhook.host_str(), Some("127.0.0.1") | Some("localhost"));
+        match webhook.scheme() {
+            "https" => Ok(()),
+            "http" if local => Ok(()),
+            _ => Err("Webhook must use HTTPS".to_owned()),
+        }
+    }
+}
+
+/// State of a rule, stored under `state:<name>` in the `alerts` store.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct RuleState {
+    active: bool,
+    last_fired: Option<i64>,
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if name.is_empty() || name.len() > MAX_RULE_NAME_LENGTH || !valid_chars {
+        return Err(format!("Invalid rule name: {}", name));
+    }
+    Ok(())
+}
+
+pub fn list() -> anyhow::Result<Value> {
+    let store = Store::open(STORE_NAME)?;
+    let mut rules = serde_json::Map::new();
+    for key in store.get_keys()? {
+        let Some(name) = key.strip_prefix(RULE_PREFIX) else {
+            continue;
+        };
+        let Some(rule) = store.get_json::<Rule>(&key)? else {
+            continue;
+        };
+        let state = store
+            .get_json::<RuleState>(format!("{}{}", STATE_PREFIX, name))?
+            .unwrap_or_default();
+        let mut value = serde_json::to_value(&rule)?;
+        value["active"] = state.active.into();
+        value["last_fired"] = json!(state.last_fired);
+        rules.insert(name.to_owned(), value);
+    }
+    Ok(Value::Object(rules))
+}
+
+pub fn put(name: &str, rule: &Rule) -> anyhow::Result<()> {
+    let store = Store::open(STORE_NAME)?;
+    store.set_json(format!("{}{}", RULE_PREFIX, name), rule)?;
+    // conditions may have changed, so evaluation starts over
+    store.delete(&format!("{}{}", STATE_PREFIX, name))?;
+    Ok(())
+}
+
+/// Returns `false` when there was no such rule.
+pub fn delete(name: &str) -> anyhow::Result<bool> {
+    let store = Store::open(STORE_NAME)?;
+    let key = format!("{}{}", RULE_PREFIX, name);
+    if !store.exists(&key)? {
+        return Ok(false);
+    }
+    store.delete(&key)?;
+    store.delete(&format!("{}{}", STATE_PREFIX, name))?;
+    Ok(true)
+}
+
+thread_local! {
+    // fresh measurements collected while handling the current request, keyed by station URL
+    static PENDING: RefCell<Vec<(String, Value)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn record(url: &str, measurements: &Measurements) {
+    let value = serde_json::to_value(measurements).unwrap_or_default();
+    PENDING.with_borrow_mut(|pending| pending.push((url.to_owned(), value)));
+}
+
+async fn fire(name: &str, rule: &Rule, measurements: &Value) -> anyhow::Result<()> {
+    let payload = json!({
+        "rule": name,
+        "station": rule.station,
+        "message": rule.message,
+        "fired_at": Utc::now().format("%Y-%m-%d %H:%M").to_string(),
+        "measurements": measurements,
+    });
+    let delivery = json!({ "webhook": rule.webhook, "payload": payload });
+    let request = Request::builder()
+        .method(Method::Post)
+        .uri(DELIVERY_URL)
+        .header("content-type", "application/json")
+        .body(delivery.to_string())
+        .build();
+
+    let response: Response = spin_sdk::http::send(request).await?;
+    if !(200..300).contains(response.status()) {
+        let body = String::from_utf8_lossy(response.body());
+        anyhow::bail!("Webhook delivery failed with {}: {}", response.status(), body);
+    }
+    Ok(())
+}
+
+/// Checks rules of the stations fetched while handling the current request.
+///
+/// Webhook failures are logged only, the rule is still considered fired.
+pub async fn flush() -> anyhow::Result<()> {
+    let pending = PENDING.take();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let store = Store::open(STORE_NAME)?;
+    let now = Utc::now().timestamp();
+    for key in store.get_keys()? {
+        let Some(name) = key.strip_prefix(RULE_PREFIX) else {
+            continue;
+        };
+        let Some(rule) = store.get_json::<Rule>(&key)? else {
+            continue;
+        };
+        let Some((_, measurements)) = pending.iter().find(|(url, _)| *url == rule.station) else {
+            continue;
+        };
+
+        let state_key = format!("{}{}", STATE_PREFIX, name);
+        let mut state = store.get_json::<RuleState>(&state_key)?.unwrap_or_default();
+        let holds = rule
+            .conditions
+            .iter()
+            .map(|c| c.holds(measurements, state.active))
+            .collect::<Option<Vec<_>>>();
+        // missing readings leave the state as it is
+        let Some(holds) = holds else {
+            continue;
+        };
+        let active = holds.into_iter().all(|h| h);
+
+        let cooled_down = state
+            .last_fired
+            .map_or(true, |t| now - t >= rule.cooldown_minutes as i64 * 60);
+        let was_active = state.active;
+        state.active = active;
+        if active && !was_active && cooled_down {
+            log::info!("Alert rule fired: {}", name);
+            state.last_fired = Some(now);
+            if let Err(e) = fire(name, &rule, measurements).await {
+                log::error!("{} while calling webhook of rule: {}", e, name);
+            }
+        }
+        if active != was_active {
+            store.set_json(&state_key, &state)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use crate::plain_text_resp;
+use crate::ratelimit::Limit;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use spin_sdk::http::{Request, Response};
+use spin_sdk::key_value::Store;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STORE_NAME: &str = "auth";
+const API_KEY_PREFIX: &str = "apikey:";
+const STATION_KEY_PREFIX: &str = "station:";
+const SESSION_TTL_SECS: u64 = 15 * 60;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Admin,
+}
+
+// Stored under `apikey:<name>` in the `auth` store
+#[derive(Deserialize, Debug)]
+struct ApiKey {
+    secret_sha256: String,
+    scopes: Vec<Scope>,
+    #[serde(default)]
+    revoked: bool,
+    // requests per minute, a default applies when missing
+    rate_limit_per_minute: Option<f64>,
+}
+
+// Stored under `station:<id>` in the `auth` store
+#[derive(Deserialize, Debug)]
+struct StationKey {
+    secret_sha256: String,
+    #[serde(default)]
+    revoked: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Principal {
+    ApiKey {
+        name: String,
+        scopes: Vec<Scope>,
+        rate_limit: Limit,
+    },
+    Session,
+}
+
+impl Principal {
+    pub fn name(&self) -> &str {
+        match self {
+            Principal::ApiKey { name, .. } => name,
+            Principal::Session => "session",
+        }
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match self {
+            Principal::ApiKey { scopes, .. } => scopes.contains(&scope),
+            Principal::Session => scope == Scope::Read,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn session_mac(expires_at: u64) -> anyhow::Result<HmacSha256> {
+    let secret = spin_sdk::variables::get("session_secret")?;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("session.{}", expires_at).as_bytes());
+    Ok(mac)
+}
+
+/// Pseudonymous ID of a client address, stable as long as the session secret is.
+pub fn anonymize(client_addr: &str) -> anyhow::Result<String> {
+    let secret = spin_sdk::variables::get("session_secret")?;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("client.{}", client_addr).as_bytes());
+    let digest = mac.finalize().into_bytes();
+    Ok(to_hex(&digest[..8]))
+}
+
+pub fn issue_session() -> anyhow::Result<Session> {
+    let expires_at = now() + SESSION_TTL_SECS;
+    let signature = session_mac(expires_at)?.finalize().into_bytes();
+    Ok(Session {
+        token: format!("{}.{}", expires_at, to_hex(&signature)),
+        expires_at,
+    })
+}
+
+fn verify_session(token: &str) -> anyhow::Result<Option<Principal>> {
+    let Some((expires_at, signature)) = token.split_once('.') else {
+        return Ok(None);
+    };
+    let (Ok(expires_at), Some(signature)) = (expires_at.parse::<u64>(), from_hex(signature)) else {
+        return Ok(None);
+    };
+    if expires_at <= now() {
+        return Ok(None);
+    }
+    // `verify_slice` compares in constant time
+    match session_mac(expires_at)?.verify_slice(&signature) {
+        Ok(()) => Ok(Some(Principal::Session)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn verify_api_key(key: &str) -> anyhow::Result<Option<Principal>> {
+    let Some((name, secret)) = key.split_once(':') else {
+        return Ok(None);
+    };
+    let store = Store::open(STORE_NAME)?;
+    let Some(api_key) = store.get_json::<ApiKey>(format!("{}{}", API_KEY_PREFIX, name))? else {
+        return Ok(None);
+    };
+
+    let secret_sha256 = Sha256::digest(secret.as_bytes());
+    let expected = from_hex(&api_key.secret_sha256).unwrap_or_default();
+    if !constant_time_eq(&secret_sha256, &expected) {
+        return Ok(None);
+    }
+    if api_key.revoked {
+        log::warn!("Revoked API key used: {}", name);
+        return Ok(None);
+    }
+    Ok(Some(Principal::ApiKey {
+        name: name.to_owned(),
+        scopes: api_key.scopes,
+        rate_limit: Limit::per_key(api_key.rate_limit_per_minute),
+    }))
+}
+
+/// Principal of the `ApiKey` credentials of the request, `None` unless these are valid.
+pub fn api_key(req: &Request) -> anyhow::Result<Option<Principal>> {
+    let header = req.header("authorization").and_then(|v| v.as_str());
+    match header.and_then(|h| h.split_once(' ')) {
+        Some(("ApiKey", key)) => verify_api_key(key.trim()),
+        _ => Ok(None),
+    }
+}
+
+/// Whether a station pushing readings presented the key registered for it.
+pub fn verify_station(id: &str, secret: &str) -> anyhow::Result<bool> {
+    let store = Store::open(STORE_NAME)?;
+    let Some(station_key) = store.get_json::<StationKey>(format!("{}{}", STATION_KEY_PREFIX, id))?
+    else {
+        return Ok(false);
+    };
+
+    let secret_sha256 = Sha256::digest(secret.as_bytes());
+    let expected = from_hex(&station_key.secret_sha256).unwrap_or_default();
+    if !constant_time_eq(&secret_sha256, &expected) {
+        return Ok(false);
+    }
+    if station_key.revoked {
+        log::warn!("Revoked station key used: {}", id);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn unauthorized_resp(message: &str) -> Response {
+    Response::builder()
+        .status(401)
+        .header("content-type", "text/plain")
+        .header("www-authenticate", "Bearer, ApiKey")
+        .body(message)
+        .build()
+}
+
+/// Resolves the `Authorization` header into a principal holding the given scope.
+///
+/// Accepts `Bearer <session token>` issued by [`issue_session`]
+/// and `ApiKey <name>:<secret>` for keys stored in the `auth` store.
+pub fn authorize(req: &Request, scope: Scope) -> anyhow::Result<Result<Principal, Response>> {
+    let Some(header) = req.header("authorization").and_then(|v| v.as_str()) else {
+        log::error!("Missing credentials");
+        return Ok(Err(unauthorized_resp("Missing credentials")));
+    };
+
+    let principal = match header.split_once(' ') {
+        Some(("Bearer", token)) => verify_session(token.trim())?,
+        Some(("ApiKey", key)) => verify_api_key(key.trim())?,
+        _ => None,
+    };
+
+    match principal {
+        Some(principal) if principal.has_scope(scope) => Ok(Ok(principal)),
+        Some(principal) => {
+            log::error!("Insufficient scope: {:?} for {:?}", scope, principal);
+            Ok(Err(plain_text_resp(403, "Insufficient scope")))
+        }
+        None => {
+            log::error!("Invalid credentials");
+            Ok(Err(unauthorized_resp("Invalid credentials")))
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::measurements::{Fields, Measurements};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use spin_sdk::key_value::Store;
+
+const STORE_NAME: &str = "cache";
+const KEY_PREFIX: &str = "station:";
+const INFLIGHT_PREFIX: &str = "inflight:";
+
+// a fetch not finished by then is considered abandoned
+const INFLIGHT_TIMEOUT_SECS: i64 = 30;
+
+// how long after expiry the readings may still be served while they are being refreshed
+const MAX_STALENESS_SECS: i64 = 600;
+
+/// Latest measurements of a station, stored under `station:<url>` in the `cache` store.
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    fetched_at: i64,
+    // fields that were requested when fetching
+    #[serde(default)]
+    fields: Fields,
+    measurements: Measurements,
+}
+
+#[derive(Debug)]
+pub enum Lookup {
+    Fresh(Measurements),
+    /// Expired, but still good enough when a refresh is in progress
+    Stale(Measurements),
+    Miss,
+}
+
+/// Cached measurements of the station covering `fields`, fresh when younger than `max_age_secs`.
+pub fn lookup(url: &str, max_age_secs: i64, fields: &Fields) -> anyhow::Result<Lookup> {
+    let store = Store::open(STORE_NAME)?;
+    let Some(entry) = store.get_json::<Entry>(format!("{}{}", KEY_PREFIX, url))? else {
+        return Ok(Lookup::Miss);
+    };
+    if !entry.fields.covers(fields) {
+        return Ok(Lookup::Miss);
+    }
+
+    let age = Utc::now().timestamp() - entry.fetched_at;
+    let lookup = if age < max_age_secs {
+        Lookup::Fresh(entry.measurements)
+    } else if age < max_age_secs + MAX_STALENESS_SECS {
+        Lookup::Stale(entry.measurements)
+    } else {
+        Lookup::Miss
+    };
+    Ok(lookup)
+}
+
+pub fn put(url: &str, fields: &Fields, measurements: &Measurements) -> anyhow::Result<()> {
+    let store = Store::open(STORE_NAME)?;
+    let entry = Entry {
+        fetched_at: Utc::now().timestamp(),
+        fields: fields.clone(),
+        measurements: measurements.clone(),
+    };
+    store.set_json(format!("{}{}", KEY_PREFIX, url), &entry)?;
+    Ok(())
+}
+
+/// Marks the station as being fetched, unless another request is already fetching it.
+///
+/// Returns `false` in the latter case. The check and the update are not atomic, so
+/// occasionally two requests fetch the same station. This is fine for the purpose.
+pub fn claim(url: &str) -> anyhow::Result<bool> {
+    let store = Store::open(STORE_NAME)?;
+    let key = format!("{}{}", INFLIGHT_PREFIX, url);
+    let now = Utc::now().timestamp();
+    if let Some(started) = store.get_json::<i64>(&key)? {
+        if now - started < INFLIGHT_TIMEOUT_SECS {
+            return Ok(false);
+        }
+    }
+    store.set_json(&key, &now)?;
+    Ok(true)
+}
+
+/// Whether another request is still fetching the station
+pub fn in_flight(url: &str) -> anyhow::Result<bool> {
+    let store = Store::open(STORE_NAME)?;
+    let key = format!("{}{}", INFLIGHT_PREFIX, url);
+    let now = Utc::now().timestamp();
+    let started = store.get_json::<i64>(&key)?;
+    Ok(started.is_some_and(|started| now - started < INFLIGHT_TIMEOUT_SECS))
+}
+
+pub fn release(url: &str) -> anyhow::Result<()> {
+    let store = Store::open(STORE_NAME)?;
+    store.delete(&format!("{}{}", INFLIGHT_PREFIX, url))?;
+    Ok(())
+}
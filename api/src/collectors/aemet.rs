@@ -1,13 +1,18 @@
+use crate::collectors::common::wind_direction_name;
 use crate::collectors::Downloader;
-use crate::measurements::Measurements;
+use crate::measurements::{Fields, Measurements};
 use anyhow::{anyhow, Context};
 use chrono::NaiveDateTime;
 use chrono::TimeZone;
 use chrono_tz::Europe::Madrid;
 use scraper::{Html, Selector};
+use serde::Deserialize;
 use spin_sdk::http::{Method, Request, Response};
 
 pub const BASE_URL: &str = "https://www.aemet.es/";
+const OPENDATA_URL: &str = "https://opendata.aemet.es/opendata/";
+
+const KMH_PER_MS: f64 = 3.6;
 
 pub struct AemetDownloader {}
 
@@ -15,12 +20,145 @@ fn parse_selector(selector: &str) -> anyhow::Result<Selector> {
     Selector::parse(selector).map_err(|e| anyhow!(e.to_string()))
 }
 
+/// Envelope of OpenData responses, the data itself is to be downloaded from `datos`
+#[derive(Deserialize, Debug)]
+struct OpenDataResponse {
+    estado: u16,
+    descripcion: String,
+    datos: Option<String>,
+}
+
+/// Hourly observation of a station, speeds are in m/s
+#[derive(Deserialize, Debug)]
+struct Observation {
+    fint: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    prec: Option<f64>,
+    vv: Option<f64>,
+    vmax: Option<f64>,
+    dv: Option<f64>,
+    pres: Option<f64>,
+    pres_nmar: Option<f64>,
+    hr: Option<f64>,
+    ta: Option<f64>,
+    tpr: Option<f64>,
+}
+
+/// Latest of the observations returned by the OpenData API, the body is expected as text.
+pub fn parse_observations(body: &str) -> anyhow::Result<Measurements> {
+    let observations: Vec<Observation> =
+        serde_json::from_str(body).context("Observations parsing failed")?;
+    // times are in UTC and sort as text
+    let latest = observations
+        .into_iter()
+        .max_by(|a, b| a.fint.cmp(&b.fint))
+        .ok_or(anyhow!("No observations"))?;
+
+    let update_time = NaiveDateTime::parse_from_str(&latest.fint, "%Y-%m-%dT%H:%M:%S")
+        .context("Timestamp parsing failed")?;
+    let kmh = |speed: f64| (speed * KMH_PER_MS).round() as u64;
+
+    Ok(Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        humidity: latest.hr.map(|v| v.round() as u64),
+        precipitation: latest.prec,
+        pressure: latest.pres.map(|p| p.round() as u64),
+        temperature: latest.ta,
+        wind_direction: latest.dv.map(|d| wind_direction_name(d).to_owned()),
+        wind_speed: latest.vv.map(kmh),
+        gusts_speed: latest.vmax.map(kmh),
+        dew_point: latest.tpr,
+        pressure_sea_level: latest.pres_nmar.map(|p| p.round() as u64),
+        latitude: latest.lat,
+        longitude: latest.lon,
+        ..Default::default()
+    })
+}
+
+async fn get_opendata(url: &str, api_key: &str) -> anyhow::Result<String> {
+    let request = Request::builder()
+        .method(Method::Get)
+        .header("api_key", api_key)
+        .uri(url)
+        .build();
+
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected response status: {}", response.status());
+    }
+    // responses are encoded in ISO-8859-15, regardless of what headers say
+    let (body, _, _) = encoding_rs::ISO_8859_15.decode(response.body());
+    Ok(body.into_owned())
+}
+
+async fn download_opendata(url: &str, api_key: &str) -> anyhow::Result<Measurements> {
+    let station_id = url::Url::parse(url)?
+        .query_pairs()
+        .find(|(key, _)| key == "l")
+        .map(|(_, id)| id.into_owned())
+        .ok_or(anyhow!("Station ID not found in: {}", url))?;
+
+    let url = format!(
+        "{}api/observacion/convencional/datos/estacion/{}",
+        OPENDATA_URL, station_id
+    );
+    let body = get_opendata(&url, api_key).await?;
+    let response: OpenDataResponse = serde_json::from_str(&body)?;
+    let datos = match response.datos {
+        Some(datos) if response.estado == 200 => datos,
+        _ => anyhow::bail!("OpenData error {}: {}", response.estado, response.descripcion),
+    };
+
+    let body = get_opendata(&datos, api_key).await?;
+    parse_observations(&body)
+}
+
 impl Downloader for AemetDownloader {
+    fn name(&self) -> &'static str {
+        "aemet"
+    }
+
     fn base_url(&self) -> String {
         BASE_URL.to_owned()
     }
 
-    async fn try_download(&self, url: &str) -> anyhow::Result<Measurements> {
+    fn station_url(&self, id: &str) -> String {
+        format!("{}en/eltiempo/observacion/ultimosdatos?l={}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        // the extra ones only when using OpenData
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "pressure",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "dew_point",
+            "pressure_sea_level",
+            "latitude",
+            "longitude",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        // observations are published hourly
+        60
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let api_key = spin_sdk::variables::get("aemet_api_key").unwrap_or_default();
+        if !api_key.is_empty() {
+            match download_opendata(url, &api_key).await {
+                Ok(measurements) => return Ok(measurements),
+                Err(e) => log::warn!("{} while using OpenData, falling back to HTML: {}", e, url),
+            }
+        }
+
         let url = format!("{}&w=0&datos=det", url);
         let request = Request::builder()
             .method(Method::Get)
@@ -119,8 +257,67 @@ impl Downloader for AemetDownloader {
             wind_direction: wind_direction.map(|s| s.to_owned()),
             wind_speed,
             gusts_speed,
+            ..Default::default()
         };
 
         Ok(measurements)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the last hours of Madrid Retiro, in UTC and in ascending order
+    const OBSERVATIONS: &str = include_str!("fixtures/aemet_observations.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn latest_observation() {
+        let m = parse_observations(OBSERVATIONS).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 11:00"));
+        assert_close(m.temperature, 24.3);
+        assert_eq!(m.humidity, Some(41));
+        assert_close(m.precipitation, 0.2);
+        assert_eq!(m.pressure, Some(944));
+        assert_eq!(m.pressure_sea_level, Some(1017));
+        assert_close(m.dew_point, 10.2);
+        // speeds in m/s, 3.1 and 7.4
+        assert_eq!(m.wind_speed, Some(11));
+        assert_eq!(m.gusts_speed, Some(27));
+        assert_eq!(m.wind_direction.as_deref(), Some("SW"));
+        assert_close(m.latitude, 40.411804);
+        assert_close(m.longitude, -3.678064);
+    }
+
+    #[test]
+    fn missing_sensors() {
+        // the station has no anemometer nor barometer
+        let body = r#"[
+            {"idema": "3194U", "fint": "2024-06-14T11:00:00", "ta": 24.9, "hr": 39.0},
+            {"idema": "3194U", "fint": "2024-06-14T10:00:00", "ta": 23.6, "hr": 44.0}
+        ]"#;
+        let m = parse_observations(body).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 11:00"));
+        assert_close(m.temperature, 24.9);
+        assert_eq!(m.humidity, Some(39));
+        assert_eq!(m.pressure, None);
+        assert_eq!(m.wind_speed, None);
+        assert_eq!(m.wind_direction, None);
+    }
+
+    #[test]
+    fn no_observations() {
+        assert!(parse_observations("[]").is_err());
+        assert!(parse_observations(r#"[{"fint": "14/06/2024 11:00"}]"#).is_err());
+    }
+}
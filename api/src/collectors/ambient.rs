@@ -0,0 +1,194 @@
+use crate::collectors::common::wind_direction_name;
+use crate::collectors::units;
+use crate::collectors::Downloader;
+use crate::measurements::{Fields, Measurements};
+use anyhow::{anyhow, Context};
+use chrono::DateTime;
+use serde::Deserialize;
+use spin_sdk::http::{Method, Request, Response};
+
+pub const BASE_URL: &str = "https://ambientweather.net/";
+const API_URL: &str = "https://lightning.ambientweather.net/devices";
+
+pub struct AmbientDownloader {}
+
+#[derive(Deserialize, Debug)]
+struct DevicesRaw {
+    data: Vec<DeviceRaw>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct DeviceRaw {
+    lastData: LastDataRaw,
+    info: Option<InfoRaw>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InfoRaw {
+    coords: Option<CoordsRaw>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CoordsRaw {
+    coords: Option<LocationRaw>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LocationRaw {
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+// the unit is given by the suffix of the key, outdoor sensors only
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct LastDataRaw {
+    dateutc: i64,
+    tempf: Option<f64>,
+    humidity: Option<f64>,
+    windspeedmph: Option<f64>,
+    windgustmph: Option<f64>,
+    winddir: Option<f64>,
+    baromabsin: Option<f64>,
+    baromrelin: Option<f64>,
+    dailyrainin: Option<f64>,
+    dewPoint: Option<f64>,
+}
+
+/// Slug of the public dashboard, e.g. `2b5a3e8c`
+fn slug(url: &str) -> Option<&str> {
+    let (_, slug) = url.split_once("/dashboard/")?;
+    let slug = slug.split(['/', '?', '#']).next()?;
+    (!slug.is_empty() && slug.chars().all(|c| c.is_ascii_alphanumeric())).then_some(slug)
+}
+
+/// Readings of a station given the response of the public devices API
+pub fn parse_devices(body: &str) -> anyhow::Result<Measurements> {
+    let raw: DevicesRaw = serde_json::from_str(body).context("Devices parsing failed")?;
+    let device = raw
+        .data
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No devices"))?;
+    let data = device.lastData;
+
+    // milliseconds since the epoch
+    let update_time = DateTime::from_timestamp_millis(data.dateutc)
+        .ok_or_else(|| anyhow!("Invalid timestamp"))?;
+    let temperature = |t: f64| units::temperature(t, "F");
+    let speed = |s: f64| units::speed(s, "mph").map(|s| s.round() as u64);
+    let pressure = |p: f64| units::pressure(p, "inHg").map(|p| p.round() as u64);
+    let location = device
+        .info
+        .and_then(|info| info.coords)
+        .and_then(|coords| coords.coords);
+
+    Ok(Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        humidity: data.humidity.map(|v| v.round() as u64),
+        precipitation: data.dailyrainin.and_then(|p| units::precipitation(p, "in")),
+        pressure: data.baromabsin.and_then(pressure),
+        temperature: data.tempf.and_then(temperature),
+        wind_direction: data.winddir.map(|d| wind_direction_name(d).to_owned()),
+        wind_speed: data.windspeedmph.and_then(speed),
+        gusts_speed: data.windgustmph.and_then(speed),
+        dew_point: data.dewPoint.and_then(temperature),
+        pressure_sea_level: data.baromrelin.and_then(pressure),
+        latitude: location.as_ref().and_then(|l| l.lat),
+        longitude: location.as_ref().and_then(|l| l.lon),
+        ..Default::default()
+    })
+}
+
+impl Downloader for AmbientDownloader {
+    fn name(&self) -> &'static str {
+        "ambient"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_owned()
+    }
+
+    fn station_url(&self, id: &str) -> String {
+        format!("{}dashboard/{}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "pressure",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "dew_point",
+            "pressure_sea_level",
+            "latitude",
+            "longitude",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        5
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let slug = slug(url).ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+        let api_url = format!("{}?public.slug={}", API_URL, slug);
+
+        let request = Request::builder().method(Method::Get).uri(api_url).build();
+
+        let response: Response = spin_sdk::http::send(request).await?;
+        if *response.status() != 200 {
+            anyhow::bail!("Unexpected response status: {}", response.status());
+        }
+        parse_devices(&String::from_utf8_lossy(response.body()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a public device, readings in imperial units and keyed by unit
+    const DEVICES: &str = include_str!("fixtures/ambient_devices.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn outdoor_readings() {
+        let m = parse_devices(DEVICES).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 10:31"));
+        // 68.9 and 55.4 °F, not those of the indoor sensor
+        assert_close(m.temperature, 20.5);
+        assert_close(m.dew_point, 13.0);
+        assert_eq!(m.humidity, Some(62));
+        // 9.17 and 13.87 mph
+        assert_eq!(m.wind_speed, Some(15));
+        assert_eq!(m.gusts_speed, Some(22));
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        // absolute and relative, 28.532 and 29.921 inHg
+        assert_eq!(m.pressure, Some(966));
+        assert_eq!(m.pressure_sea_level, Some(1013));
+        // of the day, 0.13 in
+        assert_close(m.precipitation, 3.3);
+        assert_close(m.latitude, 39.7392);
+        assert_close(m.longitude, -104.9903);
+    }
+
+    #[test]
+    fn no_devices() {
+        assert!(parse_devices(r#"{"data": []}"#).is_err());
+    }
+}
@@ -1,31 +1,153 @@
-use crate::measurements::Measurements;
+use crate::measurements::{Fields, Measurements};
+use crate::ratelimit::{self, Limit};
+use crate::{alerts, health, stats, MAX_CONCURRENT_DOWNLOADS};
+use anyhow::anyhow;
+use futures::stream::{self, StreamExt};
+use std::time::{Duration, Instant};
+
 pub trait Downloader {
+    fn name(&self) -> &'static str;
     fn base_url(&self) -> String;
-    async fn try_download(&self, url: &str) -> anyhow::Result<Measurements>;
+    /// URL of the station page given the ID used by the provider
+    fn station_url(&self, id: &str) -> String;
+    /// Keys of `Measurements` the provider is able to fill in
+    fn provided_fields(&self) -> &'static [&'static str];
+    /// Whether the provider fills in any of the requested fields
+    fn provides(&self, fields: &Fields) -> bool {
+        fields.contains_any(self.provided_fields())
+    }
+    /// How often the provider publishes new readings
+    fn update_interval_minutes(&self) -> i64 {
+        10
+    }
+    /// Forecasts are predictions for coordinates, they are kept out of the history
+    fn is_forecast(&self) -> bool {
+        false
+    }
+    /// Fills in the keys that depend on the time they are served at, e.g. forecasts ahead
+    fn resolve(&self, _measurements: &mut Measurements) {}
+    /// Fields that were not requested may be left empty to save work
+    async fn try_download(&self, url: &str, fields: &Fields) -> anyhow::Result<Measurements>;
+
+    async fn download(&self, url: &str, fields: &Fields) -> anyhow::Result<Measurements> {
+        if !self.take_upstream(url) {
+            return Err(anyhow!("Upstream limit of {} reached", self.name()));
+        }
+        let started = Instant::now();
+        let payload = self.try_download(url, fields).await;
+        self.finish(url, started.elapsed(), payload)
+    }
+
+    /// Downloads several stations of the provider, in the order of `urls`.
+    ///
+    /// Providers that list many stations in a shared resource can fetch it once instead.
+    async fn download_batch(
+        &self,
+        urls: &[&str],
+        fields: &Fields,
+    ) -> Vec<anyhow::Result<Measurements>> {
+        let results = urls.iter().map(|_| None).collect();
+        self.download_remaining(urls, results, fields).await
+    }
 
-    async fn download(&self, url: &str) -> Measurements {
-        let payload = self.try_download(url).await;
+    /// Downloads one by one the stations that have no readings in `results` yet
+    async fn download_remaining(
+        &self,
+        urls: &[&str],
+        mut results: Vec<Option<anyhow::Result<Measurements>>>,
+        fields: &Fields,
+    ) -> Vec<anyhow::Result<Measurements>> {
+        let missing = urls
+            .iter()
+            .zip(results.iter())
+            .filter(|(_, result)| result.is_none())
+            .map(|(url, _)| *url)
+            .collect::<Vec<_>>();
+        let mut downloaded = stream::iter(missing)
+            .map(|url| self.download(url, fields))
+            .buffered(MAX_CONCURRENT_DOWNLOADS)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter();
+
+        for result in results.iter_mut().filter(|result| result.is_none()) {
+            *result = downloaded.next();
+        }
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(anyhow!("Not downloaded"))))
+            .collect()
+    }
+
+    /// Takes a token of the upstream limit of the provider, one per request sent to it.
+    ///
+    /// Returns `false` once the limit is reached, `what` is only logged.
+    fn take_upstream(&self, what: &str) -> bool {
+        let key = format!("provider:{}", self.name());
+        match ratelimit::take(&key, Limit::per_provider()) {
+            Ok(None) => true,
+            Ok(Some(_)) => {
+                log::warn!("Upstream limit of {} reached, skipping: {}", self.name(), what);
+                false
+            }
+            Err(e) => {
+                log::error!("{} while checking upstream limit of {}", e, self.name());
+                true
+            }
+        }
+    }
+
+    /// Records the outcome of a download, which is passed through
+    fn finish(
+        &self,
+        url: &str,
+        latency: Duration,
+        payload: anyhow::Result<Measurements>,
+    ) -> anyhow::Result<Measurements> {
+        stats::record_fetch(self.name(), url, latency, payload.is_ok());
+        health::record(self.name(), url, payload.as_ref().err());
         match payload {
             Ok(payload) => {
                 log::info!("Downloaded: {}", url);
-                payload
+                alerts::record(url, &payload);
+                Ok(payload)
             }
-            Err(ref e) => {
+            Err(e) => {
                 log::error!("{} while downloading: {}", e, url);
-                Measurements::default()
+                Err(e)
             }
         }
     }
 }
 
+pub const ALL_FIELDS: &[&str] = &[
+    "update_time",
+    "humidity",
+    "precipitation",
+    "pressure",
+    "temperature",
+    "wind_direction",
+    "wind_speed",
+    "gusts_speed",
+];
+
+const DIRECTIONS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
 pub fn wind_direction_name(degrees: f64) -> &'static str {
-    const DIRECTIONS: [&str; 16] = [
-        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
-        "NW", "NNW",
-    ];
     // Normalize degrees to [0, 360)
     let deg = degrees.rem_euclid(360.0);
     // Each sector is 22.5 degrees
     let idx = ((deg + 11.25) / 22.5).floor() as usize % 16;
     DIRECTIONS[idx]
-}
\ No newline at end of file
+}
+
+/// Center of the sector given by `wind_direction_name`, e.g. 22.5 for "NNE"
+pub fn wind_direction_degrees(name: &str) -> Option<f64> {
+    DIRECTIONS
+        .iter()
+        .position(|d| d.eq_ignore_ascii_case(name.trim()))
+        .map(|idx| idx as f64 * 22.5)
+}
@@ -0,0 +1,275 @@
+use crate::collectors::common::wind_direction_name;
+use crate::collectors::Downloader;
+use crate::measurements::{Fields, Measurements};
+use anyhow::{anyhow, Context};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use spin_sdk::http::{Method, Request, Response};
+use std::collections::HashMap;
+use std::time::Instant;
+
+pub const BASE_URL: &str = "https://holfuy.com/";
+const API_URL: &str = "https://api.holfuy.com/live/";
+
+pub struct HolfuyDownloader {}
+
+/// Response of the live API, a single station is not wrapped in a list
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum LiveRaw {
+    Many { measurements: Vec<StationRaw> },
+    One(StationRaw),
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct StationRaw {
+    stationId: u64,
+    dateTime: String,
+    wind: Option<WindRaw>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    rain: Option<f64>,
+    location: Option<LocationRaw>,
+}
+
+#[derive(Deserialize, Debug)]
+struct WindRaw {
+    speed: Option<f64>,
+    gust: Option<f64>,
+    direction: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LocationRaw {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+/// Station ID given the URL of the station page, e.g. `101`
+fn station_id(url: &str) -> Option<&str> {
+    let (_, id) = url.split_once("/weather/")?;
+    let id = id.split(['/', '?', '#']).next()?;
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_digit())).then_some(id)
+}
+
+/// Readings of the stations in a response of the live API, keyed by station ID.
+pub fn parse_live(body: &str) -> anyhow::Result<HashMap<String, Measurements>> {
+    let raw: LiveRaw = serde_json::from_str(body).context("Live data parsing failed")?;
+    let stations = match raw {
+        LiveRaw::Many { measurements } => measurements,
+        LiveRaw::One(station) => vec![station],
+    };
+
+    let mut readings = HashMap::new();
+    for station in stations {
+        // times are requested in UTC, stations without a valid one are left out
+        let Ok(update_time) = NaiveDateTime::parse_from_str(&station.dateTime, "%Y-%m-%d %H:%M:%S")
+        else {
+            log::warn!(
+                "Invalid time of station {}: {}",
+                station.stationId,
+                station.dateTime
+            );
+            continue;
+        };
+        let wind = station.wind.as_ref();
+        let measurements = Measurements {
+            update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+            humidity: station.humidity.map(|v| v.round() as u64),
+            precipitation: station.rain,
+            pressure: station.pressure.map(|v| v.round() as u64),
+            temperature: station.temperature,
+            wind_direction: wind
+                .and_then(|w| w.direction)
+                .map(|d| wind_direction_name(d).to_owned()),
+            wind_speed: wind.and_then(|w| w.speed).map(|v| v.round() as u64),
+            gusts_speed: wind.and_then(|w| w.gust).map(|v| v.round() as u64),
+            latitude: station.location.as_ref().and_then(|l| l.latitude),
+            longitude: station.location.as_ref().and_then(|l| l.longitude),
+            ..Default::default()
+        };
+        readings.insert(station.stationId.to_string(), measurements);
+    }
+    Ok(readings)
+}
+
+/// Readings of the stations, all of them are fetched with a single request
+async fn download_live(ids: &[&str]) -> anyhow::Result<HashMap<String, Measurements>> {
+    let api_key = spin_sdk::variables::get("holfuy_api_key").unwrap_or_default();
+    if api_key.is_empty() {
+        anyhow::bail!("Holfuy API key not set");
+    }
+
+    let mut url = url::Url::parse(API_URL)?;
+    url.query_pairs_mut()
+        .append_pair("s", &ids.join(","))
+        .append_pair("pw", &api_key)
+        .append_pair("m", "JSON")
+        .append_pair("tu", "C")
+        .append_pair("su", "km/h")
+        .append_pair("utc", "")
+        .append_pair("loc", "");
+
+    let request = Request::builder()
+        .method(Method::Get)
+        .uri(url.as_str())
+        .build();
+
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected response status: {}", response.status());
+    }
+    parse_live(&String::from_utf8_lossy(response.body()))
+}
+
+impl Downloader for HolfuyDownloader {
+    fn name(&self) -> &'static str {
+        "holfuy"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_owned()
+    }
+
+    fn station_url(&self, id: &str) -> String {
+        format!("{}en/weather/{}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "pressure",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "latitude",
+            "longitude",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        // most stations report every minute, the API refreshes more slowly
+        2
+    }
+
+    // the API takes a list of stations, so there is no point in asking one by one
+    async fn download_batch(
+        &self,
+        urls: &[&str],
+        _fields: &Fields,
+    ) -> Vec<anyhow::Result<Measurements>> {
+        let ids = urls.iter().map(|url| station_id(url)).collect::<Vec<_>>();
+        let mut valid = ids.iter().flatten().copied().collect::<Vec<_>>();
+        valid.sort_unstable();
+        valid.dedup();
+        // the stale readings are served instead, this is not a failure of the stations
+        if !valid.is_empty() && !self.take_upstream("live data") {
+            return urls
+                .iter()
+                .map(|_| Err(anyhow!("Upstream limit of {} reached", self.name())))
+                .collect();
+        }
+
+        let started = Instant::now();
+        let live = if valid.is_empty() {
+            Ok(HashMap::new())
+        } else {
+            download_live(&valid).await
+        };
+        let latency = started.elapsed();
+
+        urls.iter()
+            .zip(ids)
+            .map(|(url, id)| {
+                let payload = match (&live, id) {
+                    (_, None) => Err(anyhow!("Invalid URL: {}", url)),
+                    (Ok(live), Some(id)) => live
+                        .get(id)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("Station not found: {}", id)),
+                    (Err(e), Some(_)) => Err(anyhow!("{}", e)),
+                };
+                self.finish(url, latency, payload)
+            })
+            .collect()
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let id = station_id(url).ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+        download_live(&[id])
+            .await?
+            .remove(id)
+            .ok_or_else(|| anyhow!("Station not found: {}", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two stations, one of them without humidity, pressure and rain sensors, and one
+    // offline with a zeroed time
+    const LIVE: &str = include_str!("fixtures/holfuy_live.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn readings_per_station() {
+        let readings = parse_live(LIVE).unwrap();
+        let m = &readings["101"];
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 10:31"));
+        assert_eq!(m.wind_speed, Some(13));
+        assert_eq!(m.gusts_speed, Some(20));
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        assert_close(m.temperature, 21.3);
+        assert_eq!(m.humidity, Some(58));
+        assert_eq!(m.pressure, Some(1014));
+        assert_close(m.precipitation, 0.0);
+        assert_close(m.latitude, 47.4813);
+        assert_close(m.longitude, 19.0437);
+
+        let m = &readings["1437"];
+        assert_eq!(m.wind_speed, Some(4));
+        assert_eq!(m.wind_direction.as_deref(), Some("NNE"));
+        assert_close(m.temperature, 26.7);
+        assert_eq!(m.humidity, None);
+        assert_eq!(m.pressure, None);
+        assert_eq!(m.precipitation, None);
+    }
+
+    #[test]
+    fn invalid_times() {
+        let readings = parse_live(LIVE).unwrap();
+        assert_eq!(readings.len(), 2);
+        assert!(!readings.contains_key("1702"));
+    }
+
+    #[test]
+    fn single_station() {
+        let body = r#"{"stationId": 101, "dateTime": "2024-06-14 10:31:22",
+            "wind": {"speed": 12.6, "direction": 247}}"#;
+        let readings = parse_live(body).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings["101"].wind_speed, Some(13));
+        assert_eq!(readings["101"].gusts_speed, None);
+    }
+
+    #[test]
+    fn api_error() {
+        // an error instead of readings, e.g. for a wrong key
+        assert!(parse_live(r#"{"error": "Wrong password!"}"#).is_err());
+    }
+}
@@ -1,12 +1,65 @@
 use crate::collectors::Downloader;
-use crate::measurements::Measurements;
+use crate::history;
+use crate::measurements::{Fields, Measurements};
 use crate::collectors::common::wind_direction_name;
 use anyhow::{anyhow, Context};
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime, Utc};
+use futures::future::join_all;
 use scraper::{Html, Selector};
+use serde::Deserialize;
 use spin_sdk::http::{Method, Request, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
 
 pub const BASE_URL: &str = "https://www.meteo.cat/";
+const API_URL: &str = "https://api.meteo.cat/xema/v1/";
+const OPENDATA_URL: &str = "https://analisi.transparenciacatalunya.cat/resource/";
+
+// Socrata datasets with the readings of the XEMA stations and with their metadata
+const READINGS_DATASET: &str = "nzvn-apee";
+const STATIONS_DATASET: &str = "yqwd-vj5e";
+
+// readings of the last hours are fetched as a series
+const SERIES_HOURS: i64 = 3;
+// open data may lag behind, older readings are taken from the station page instead
+const MAX_READINGS_AGE_MINUTES: i64 = 120;
+// XEMA readings are stamped with the start of the period, the station page with the end
+const READING_PERIOD_MINUTES: i64 = 30;
+const KMH_PER_MS: f64 = 3.6;
+
+// codes of the XEMA variables
+const VARIABLE_TEMPERATURE: u32 = 32;
+const VARIABLE_HUMIDITY: u32 = 33;
+const VARIABLE_PRESSURE: u32 = 34;
+const VARIABLE_PRECIPITATION: u32 = 35;
+// wind is measured at 10 m, at some stations at 6 m or 2 m instead, in order of preference
+const VARIABLES_WIND_SPEED: &[u32] = &[30, 48, 46];
+const VARIABLES_WIND_DIRECTION: &[u32] = &[31, 49, 47];
+const VARIABLES_GUSTS_SPEED: &[u32] = &[50, 53, 56];
+const VARIABLES: &[u32] = &[
+    VARIABLE_TEMPERATURE,
+    VARIABLE_HUMIDITY,
+    VARIABLE_PRESSURE,
+    VARIABLE_PRECIPITATION,
+];
+
+fn all_variables() -> Vec<u32> {
+    [
+        VARIABLES,
+        VARIABLES_WIND_SPEED,
+        VARIABLES_WIND_DIRECTION,
+        VARIABLES_GUSTS_SPEED,
+    ]
+    .concat()
+}
+
+/// Preference of a wind variable among those of other heights, 0 is the best
+fn height_rank(variable: u32) -> usize {
+    [VARIABLES_WIND_SPEED, VARIABLES_WIND_DIRECTION, VARIABLES_GUSTS_SPEED]
+        .iter()
+        .find_map(|variables| variables.iter().position(|v| *v == variable))
+        .unwrap_or_default()
+}
 
 pub struct MeteocatDownloader {}
 
@@ -14,12 +67,343 @@ fn parse_selector(selector: &str) -> anyhow::Result<Selector> {
     Selector::parse(selector).map_err(|e| anyhow!(e.to_string()))
 }
 
+/// Reading of a XEMA variable, the time is in UTC
+#[derive(Debug)]
+pub struct Reading {
+    pub station: String,
+    pub variable: u32,
+    pub time: NaiveDateTime,
+    pub value: f64,
+}
+
+/// Series of measurements per station code, oldest first.
+pub fn build_series(mut readings: Vec<Reading>) -> HashMap<String, Vec<Measurements>> {
+    // readings of the preferred heights come last, overwriting the others
+    readings.sort_by_key(|reading| std::cmp::Reverse(height_rank(reading.variable)));
+
+    let mut stations: HashMap<String, BTreeMap<NaiveDateTime, Measurements>> = HashMap::new();
+    for reading in readings {
+        let time = reading.time + Duration::minutes(READING_PERIOD_MINUTES);
+        let m = stations
+            .entry(reading.station)
+            .or_default()
+            .entry(time)
+            .or_insert_with(|| Measurements {
+                update_time: Some(time.format("%Y-%m-%d %H:%M").to_string()),
+                ..Default::default()
+            });
+
+        let value = reading.value;
+        match reading.variable {
+            VARIABLE_TEMPERATURE => m.temperature = Some(value),
+            VARIABLE_HUMIDITY => m.humidity = Some(value.round() as u64),
+            VARIABLE_PRESSURE => m.pressure = Some(value.round() as u64),
+            VARIABLE_PRECIPITATION => m.precipitation = Some(value),
+            v if VARIABLES_WIND_SPEED.contains(&v) => {
+                m.wind_speed = Some((value * KMH_PER_MS).round() as u64)
+            }
+            v if VARIABLES_WIND_DIRECTION.contains(&v) => {
+                m.wind_direction = Some(wind_direction_name(value).to_owned())
+            }
+            v if VARIABLES_GUSTS_SPEED.contains(&v) => {
+                m.gusts_speed = Some((value * KMH_PER_MS).round() as u64)
+            }
+            _ => {}
+        }
+    }
+
+    stations
+        .into_iter()
+        .map(|(station, series)| (station, series.into_values().collect()))
+        .collect()
+}
+
+/// Station code given the URL of the station page, e.g. `WM`
+fn station_code(url: &str) -> Option<String> {
+    let code = url::Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "codi")
+        .map(|(_, code)| code.to_uppercase())?;
+    // codes end up in queries, so anything unexpected is rejected
+    code.chars()
+        .all(|c| c.is_ascii_alphanumeric())
+        .then_some(code)
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    url: &str,
+    api_key: Option<&str>,
+) -> anyhow::Result<T> {
+    let mut request = Request::builder();
+    request.method(Method::Get).uri(url);
+    if let Some(api_key) = api_key {
+        request.header("X-Api-Key", api_key);
+    }
+
+    let response: Response = spin_sdk::http::send(request.build()).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected response status: {}", response.status());
+    }
+    Ok(serde_json::from_slice(response.body())?)
+}
+
+fn socrata_url(dataset: &str, filter: &str) -> anyhow::Result<String> {
+    let mut url = url::Url::parse(&format!("{}{}.json", OPENDATA_URL, dataset))?;
+    url.query_pairs_mut()
+        .append_pair("$where", filter)
+        .append_pair("$limit", "50000");
+    Ok(url.to_string())
+}
+
+fn quoted_list<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| format!("'{}'", item))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Socrata serves all columns as text
+#[derive(Deserialize, Debug)]
+struct SocrataReading {
+    codi_estacio: String,
+    codi_variable: String,
+    data_lectura: String,
+    valor_lectura: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SocrataStation {
+    codi_estacio: String,
+    latitud: String,
+    longitud: String,
+}
+
+async fn download_socrata(
+    codes: &[String],
+    since: NaiveDateTime,
+) -> anyhow::Result<Vec<Reading>> {
+    let filter = format!(
+        "codi_estacio in({}) AND codi_variable in({}) AND data_lectura >= '{}'",
+        quoted_list(codes),
+        quoted_list(&all_variables()),
+        since.format("%Y-%m-%dT%H:%M:%S"),
+    );
+    let rows: Vec<SocrataReading> = get_json(&socrata_url(READINGS_DATASET, &filter)?, None).await?;
+    Ok(socrata_readings(rows))
+}
+
+/// Readings of the Socrata rows, those with unexpected values are skipped
+fn socrata_readings(rows: Vec<SocrataReading>) -> Vec<Reading> {
+    rows.into_iter()
+        .filter_map(|row| {
+            Some(Reading {
+                station: row.codi_estacio,
+                variable: row.codi_variable.parse().ok()?,
+                time: NaiveDateTime::parse_from_str(&row.data_lectura, "%Y-%m-%dT%H:%M:%S%.f")
+                    .ok()?,
+                value: row.valor_lectura.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiStation {
+    codi: String,
+    variables: Vec<ApiVariable>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiVariable {
+    codi: u32,
+    lectures: Vec<ApiReading>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiReading {
+    data: String,
+    valor: f64,
+}
+
+// the API serves readings per station and day, so the series starts at midnight at most
+async fn download_api(
+    codes: &[String],
+    since: NaiveDateTime,
+    api_key: &str,
+) -> anyhow::Result<Vec<Reading>> {
+    let today = Utc::now().format("%Y/%m/%d");
+    let responses = join_all(codes.iter().map(|code| {
+        let url = format!("{}estacions/mesurades/{}/{}", API_URL, code, today);
+        async move { get_json::<Vec<ApiStation>>(&url, Some(api_key)).await }
+    }))
+    .await;
+
+    let stations = responses.into_iter().collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(api_readings(stations.into_iter().flatten(), since))
+}
+
+/// Readings of the API stations since the given time, those with unexpected times are skipped
+fn api_readings(
+    stations: impl IntoIterator<Item = ApiStation>,
+    since: NaiveDateTime,
+) -> Vec<Reading> {
+    let mut readings = Vec::new();
+    for station in stations {
+        for variable in station.variables {
+            for reading in variable.lectures {
+                let Ok(time) = NaiveDateTime::parse_from_str(&reading.data, "%Y-%m-%dT%H:%MZ")
+                else {
+                    continue;
+                };
+                if time >= since {
+                    readings.push(Reading {
+                        station: station.codi.clone(),
+                        variable: variable.codi,
+                        time,
+                        value: reading.valor,
+                    });
+                }
+            }
+        }
+    }
+    readings
+}
+
+async fn download_coordinates(codes: &[String]) -> anyhow::Result<HashMap<String, (f64, f64)>> {
+    let filter = format!("codi_estacio in({})", quoted_list(codes));
+    let stations: Vec<SocrataStation> =
+        get_json(&socrata_url(STATIONS_DATASET, &filter)?, None).await?;
+
+    let coordinates = stations
+        .into_iter()
+        .filter_map(|station| {
+            let latitude = station.latitud.parse().ok()?;
+            let longitude = station.longitud.parse().ok()?;
+            Some((station.codi_estacio, (latitude, longitude)))
+        })
+        .collect();
+    Ok(coordinates)
+}
+
+/// Series of the stations from the JSON API when there is a key, from Socrata otherwise.
+async fn download_xema(
+    codes: &[String],
+    fields: &Fields,
+) -> anyhow::Result<HashMap<String, Vec<Measurements>>> {
+    let since = Utc::now().naive_utc() - Duration::hours(SERIES_HOURS);
+    let api_key = spin_sdk::variables::get("meteocat_api_key").unwrap_or_default();
+    let readings = if api_key.is_empty() {
+        download_socrata(codes, since).await?
+    } else {
+        download_api(codes, since, &api_key).await?
+    };
+    let mut series = build_series(readings);
+
+    // coordinates do not change, so they cost an extra request only when asked for
+    if fields.contains_any(&["latitude", "longitude"]) {
+        match download_coordinates(codes).await {
+            Ok(coordinates) => {
+                for (code, series) in series.iter_mut() {
+                    let Some(&(latitude, longitude)) = coordinates.get(code) else {
+                        continue;
+                    };
+                    for m in series.iter_mut() {
+                        m.latitude = Some(latitude);
+                        m.longitude = Some(longitude);
+                    }
+                }
+            }
+            Err(e) => log::warn!("{} while downloading coordinates of XEMA stations", e),
+        }
+    }
+    Ok(series)
+}
+
 impl Downloader for MeteocatDownloader {
+    fn name(&self) -> &'static str {
+        "meteocat"
+    }
+
     fn base_url(&self) -> String {
         BASE_URL.to_owned()
     }
 
-    async fn try_download(&self, url: &str) -> anyhow::Result<Measurements> {
+    fn station_url(&self, id: &str) -> String {
+        format!("{}observacions/xema/dades?codi={}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        // coordinates only from open data
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "pressure",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "latitude",
+            "longitude",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        // XEMA stations report every 30 minutes
+        30
+    }
+
+    // readings come from XEMA open data, the station pages are the fallback
+    async fn download_batch(
+        &self,
+        urls: &[&str],
+        fields: &Fields,
+    ) -> Vec<anyhow::Result<Measurements>> {
+        let codes = urls.iter().map(|url| station_code(url)).collect::<Vec<_>>();
+        let mut valid = codes.iter().flatten().cloned().collect::<Vec<_>>();
+        valid.sort_unstable();
+        valid.dedup();
+
+        let started = Instant::now();
+        let mut series = if valid.is_empty() || !self.take_upstream("XEMA open data") {
+            HashMap::new()
+        } else {
+            match download_xema(&valid, fields).await {
+                Ok(series) => series,
+                Err(e) => {
+                    log::warn!("{} while downloading XEMA open data", e);
+                    HashMap::new()
+                }
+            }
+        };
+        let latency = started.elapsed();
+
+        let oldest = Utc::now().naive_utc() - Duration::minutes(MAX_READINGS_AGE_MINUTES);
+        let results = urls
+            .iter()
+            .zip(codes)
+            .map(|(url, code)| {
+                let series = series.remove(&code?)?;
+                let latest = series.last()?.clone();
+                let update_time = latest.update_time.as_deref()?;
+                let update_time = NaiveDateTime::parse_from_str(update_time, "%Y-%m-%d %H:%M");
+                if update_time.map_or(true, |t| t < oldest) {
+                    log::info!("XEMA open data lagging behind: {}", url);
+                    return None;
+                }
+                if let Err(e) = history::extend(url, &series) {
+                    log::error!("{} while saving history of: {}", e, url);
+                }
+                Some(self.finish(url, latency, Ok(latest)))
+            })
+            .collect::<Vec<_>>();
+
+        self.download_remaining(urls, results, fields).await
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
         let request = Request::builder()
             .method(Method::Get)
             .header("Accept-Charset", "utf-8")
@@ -133,8 +517,110 @@ impl Downloader for MeteocatDownloader {
             wind_direction: wind_direction.map(|s| s.to_owned()),
             wind_speed: wind_speed.map(|p| p.round() as u64),
             gusts_speed: gusts_speed.map(|p| p.round() as u64),
+            ..Default::default()
         };
 
         Ok(measurements)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two periods of el Raval, with wind measured at 6 m and humidity missing in the
+    // latter, and one of the Fabra observatory, with wind measured at 10 m and at 2 m
+    const SOCRATA: &str = include_str!("fixtures/meteocat_socrata.json");
+    // a day of el Raval as served by the API, one reading with a malformed time
+    const API: &str = include_str!("fixtures/meteocat_api.json");
+
+    fn at(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn socrata_series() -> HashMap<String, Vec<Measurements>> {
+        let rows: Vec<SocrataReading> = serde_json::from_str(SOCRATA).unwrap();
+        build_series(socrata_readings(rows))
+    }
+
+    #[test]
+    fn series_per_station() {
+        let series = socrata_series();
+        assert_eq!(series.len(), 2);
+
+        // stamped with the end of the periods, oldest first
+        let raval = &series["X4"];
+        let times = raval
+            .iter()
+            .map(|m| m.update_time.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(times, ["2024-06-14 10:30", "2024-06-14 11:00"]);
+
+        let first = &raval[0];
+        assert_close(first.temperature, 24.3);
+        assert_eq!(first.humidity, Some(58));
+        assert_eq!(first.pressure, Some(1012));
+        assert_close(first.precipitation, 0.0);
+
+        let latest = &raval[1];
+        assert_close(latest.temperature, 24.9);
+        assert_eq!(latest.pressure, Some(1012));
+        assert_close(latest.precipitation, 0.2);
+        // 3.4 and 7.2 m/s at 6 m, the only height measured
+        assert_eq!(latest.wind_speed, Some(12));
+        assert_eq!(latest.gusts_speed, Some(26));
+        assert_eq!(latest.wind_direction.as_deref(), Some("SW"));
+    }
+
+    #[test]
+    fn missing_variables() {
+        let series = socrata_series();
+        let latest = series["X4"].last().unwrap();
+        // not reported in the latter period, not carried over from the former
+        assert_eq!(latest.humidity, None);
+
+        let fabra = &series["D5"];
+        assert_eq!(fabra.len(), 1);
+        assert_close(fabra[0].temperature, 21.8);
+        assert_eq!(fabra[0].humidity, None);
+        assert_eq!(fabra[0].pressure, None);
+        assert_eq!(fabra[0].precipitation, None);
+    }
+
+    #[test]
+    fn preferred_height() {
+        let series = socrata_series();
+        // at 10 m, 5.1 and 9.8 m/s from 135 degrees, instead of the readings at 2 m
+        let fabra = &series["D5"][0];
+        assert_eq!(fabra.wind_speed, Some(18));
+        assert_eq!(fabra.gusts_speed, Some(35));
+        assert_eq!(fabra.wind_direction.as_deref(), Some("SE"));
+    }
+
+    #[test]
+    fn api_readings_since() {
+        let stations: Vec<ApiStation> = serde_json::from_str(API).unwrap();
+        let readings = api_readings(stations, at("2024-06-14 08:00"));
+        // the early one is older than the series, the one with a malformed time skipped
+        assert_eq!(readings.len(), 3);
+        assert!(readings.iter().all(|r| r.station == "X4"));
+
+        let series = build_series(readings);
+        let raval = &series["X4"];
+        assert_eq!(raval.len(), 2);
+        assert_eq!(raval[0].update_time.as_deref(), Some("2024-06-14 10:30"));
+        assert_eq!(raval[0].wind_speed, Some(10));
+        assert_close(raval[1].temperature, 24.9);
+        assert_eq!(raval[1].wind_speed, None);
+    }
+}
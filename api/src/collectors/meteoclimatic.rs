@@ -1,139 +1,469 @@
+use crate::collectors::common::wind_direction_name;
 use crate::collectors::Downloader;
-use crate::measurements::Measurements;
-use regex::Regex;
+use crate::measurements::{Fields, Measurements};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use encoding_rs::Encoding;
+use scraper::{ElementRef, Html, Selector};
 use spin_sdk::http::{Method, Request, Response};
 use std::collections::HashMap;
+use std::time::Instant;
 
 pub const BASE_URL: &str = "https://www.meteoclimatic.net/";
 
+// a regional feed is fetched only when it saves at least that many station pages
+const MIN_BATCH_SIZE: usize = 2;
+
+// the encoding has to be declared within the first 1024 bytes of the page
+const CHARSET_SNIFF_BYTES: usize = 1024;
+
+// labels of the readings on the station page, the readings themselves are in `dadesactuals`
+const LABEL_CLASS: &str = "titolet";
+const READING_CLASS: &str = "dadesactuals";
+
 pub struct MeteoclimaticDownloader {}
 
-fn parse_reading(reading: &str, unit: &str, name: &str) -> anyhow::Result<String> {
-    let re = Regex::new([r#"([-\.\d]+) *"#, unit].concat().as_str())?;
-    let reading = re
-        .captures(reading)
-        .ok_or_else(|| anyhow::anyhow!("Failed to capture {}", name))?;
+/// Station ID given the URL of the station page, e.g. `ESCAT0800000008572A`
+fn station_id(url: &str) -> Option<&str> {
+    let (_, id) = url.split_once("/perfil/")?;
+    Some(id.trim_end_matches('/'))
+}
+
+/// Region of the station, e.g. `ESCAT08` for `ESCAT0800000008572A`
+fn region(id: &str) -> Option<&str> {
+    id.get(..7)
+}
+
+/// Content of the first `name` element, tags are expected without attributes
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].trim())
+}
+
+fn number(xml: &str, path: &[&str]) -> Option<f64> {
+    let value = path.iter().try_fold(xml, |xml, name| element(xml, name))?;
+    value.replace(',', ".").parse::<f64>().ok()
+}
+
+/// Readings of the stations listed in a regional XML feed, keyed by station ID.
+pub fn parse_feed(xml: &str) -> HashMap<String, Measurements> {
+    xml.split("<station>")
+        .skip(1)
+        .filter_map(|station| {
+            let id = element(station, "id")?;
+            let update_time = element(station, "pubDate")
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc).format("%Y-%m-%d %H:%M").to_string());
+            let data = element(station, "stationdata")?;
+
+            let measurements = Measurements {
+                update_time,
+                humidity: number(data, &["humidity", "now"]).map(|v| v.round() as u64),
+                precipitation: number(data, &["rain", "total"]),
+                pressure: number(data, &["barometre", "now"]).map(|v| v.round() as u64),
+                temperature: number(data, &["temperature", "now"]),
+                wind_direction: number(data, &["wind", "azimuth"])
+                    .map(|v| wind_direction_name(v).to_owned()),
+                wind_speed: number(data, &["wind", "now"]).map(|v| v.round() as u64),
+                temperature_max: number(data, &["temperature", "max"]),
+                temperature_min: number(data, &["temperature", "min"]),
+                // the daily maximum gust, as on the station page
+                gusts_speed_max: number(data, &["wind", "max"]).map(|v| v.round() as u64),
+                ..Default::default()
+            };
+            Some((id.to_uppercase(), measurements))
+        })
+        .collect()
+}
+
+fn parse_selector(selector: &str) -> anyhow::Result<Selector> {
+    Selector::parse(selector).map_err(|e| anyhow::anyhow!(e.to_string()))
+}
+
+/// Encoding declared by the `<meta>` tag of the page, ISO-8859-15 when there is none
+fn declared_encoding(body: &[u8]) -> anyhow::Result<&'static Encoding> {
+    // the declaration itself is ASCII, whatever the encoding of the page
+    let head = &body[..body.len().min(CHARSET_SNIFF_BYTES)];
+    let (head, _, _) = encoding_rs::WINDOWS_1252.decode(head);
+    let document = Html::parse_document(&head);
+
+    let charset = parse_selector("meta[charset]")?;
+    let http_equiv = parse_selector("meta[http-equiv][content]")?;
+    let declared = document
+        .select(&charset)
+        .filter_map(|meta| meta.value().attr("charset"))
+        .chain(document.select(&http_equiv).filter_map(|meta| {
+            let content = meta.value().attr("content")?;
+            let start = content.to_lowercase().find("charset=")? + "charset=".len();
+            content.get(start..)
+        }))
+        .find_map(|label| Encoding::for_label(label.trim().as_bytes()));
+    Ok(declared.unwrap_or(encoding_rs::ISO_8859_15))
+}
+
+/// Whether the element or any of its descendants has the class
+fn has_class(element: &ElementRef, class: &str) -> bool {
+    element
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .any(|e| e.value().classes().any(|c| c == class))
+}
+
+fn cells<'a>(row: &ElementRef<'a>) -> Vec<ElementRef<'a>> {
+    row.children()
+        .filter_map(ElementRef::wrap)
+        .filter(|e| matches!(e.value().name(), "td" | "th"))
+        .collect()
+}
+
+/// Cells holding the readings of a label, up to the next label. These follow the label in its
+/// row, then a label alone in its row heads the rows below, while labels side by side head
+/// their columns only.
+fn section<'a>(label: ElementRef<'a>) -> Vec<ElementRef<'a>> {
+    let Some(cell) = std::iter::once(label)
+        .chain(label.ancestors().filter_map(ElementRef::wrap))
+        .find(|e| e.value().name() == "td")
+    else {
+        return Vec::new();
+    };
+    let Some(row) = cell.parent().and_then(ElementRef::wrap) else {
+        return Vec::new();
+    };
+    let row_cells = cells(&row);
+    let Some(column) = row_cells.iter().position(|c| c.id() == cell.id()) else {
+        return Vec::new();
+    };
+    let alone = row_cells
+        .iter()
+        .filter(|c| has_class(c, LABEL_CLASS))
+        .count()
+        == 1;
+
+    let mut section = row_cells[column + 1..]
+        .iter()
+        .take_while(|c| !has_class(c, LABEL_CLASS))
+        .copied()
+        .collect::<Vec<_>>();
+    let rows = row
+        .next_siblings()
+        .filter_map(ElementRef::wrap)
+        .filter(|e| e.value().name() == "tr");
+    for row in rows {
+        let row_cells = cells(&row);
+        if row_cells.iter().any(|c| has_class(c, LABEL_CLASS)) {
+            break;
+        }
+        if alone {
+            section.extend(row_cells);
+        } else {
+            section.extend(row_cells.get(column).copied());
+        }
+    }
+    section
+}
+
+/// First number of the text, e.g. `13,5` in `13,5 ºC`, placeholders such as `--` are not numbers.
+fn parse_number(text: &str) -> Option<f64> {
+    let is_numeric = |c: char| c.is_ascii_digit() || matches!(c, '-' | ',' | '.');
+    let start = text.find(|c: char| c.is_ascii_digit() || c == '-')?;
+    let number = text[start..].split(|c: char| !is_numeric(c)).next()?;
+    number.replace(',', ".").parse().ok()
+}
+
+/// Number following the first of the markers found in the text, e.g. `20,1` in `Máx. 20,1 ºC`
+fn number_after(text: &str, markers: &[&str]) -> Option<f64> {
+    let text = text.to_lowercase();
+    let start = markers
+        .iter()
+        .find_map(|marker| Some(text.find(marker)? + marker.len()))?;
+    parse_number(&text[start..])
+}
+
+/// Compass direction of the wind, e.g. `SW` in `SO  12 km/h`, Oeste being West
+fn parse_direction(text: &str) -> Option<String> {
+    let direction = text
+        .split(|c: char| c.is_ascii_digit())
+        .next()?
+        .trim()
+        .replace('O', "W");
+    let valid = !direction.is_empty() && direction.chars().all(|c| "NSEW".contains(c));
+    valid.then_some(direction)
+}
+
+/// Reading of a label and the text of its whole section, extremes included
+struct Reading {
+    value: Option<String>,
+    text: String,
+}
+
+/// Readings of a station given its page, labels are paired with the readings by their position
+/// in the tables, so that a missing reading does not shift the others.
+pub fn parse_page(body: &[u8]) -> anyhow::Result<Measurements> {
+    let (body, _, _) = declared_encoding(body)?.decode(body);
+    let document = Html::parse_document(&body);
+
+    let text = |element: &ElementRef| element.text().collect::<Vec<_>>().join(" ");
+    let label_selector = parse_selector(&format!(".{}", LABEL_CLASS))?;
+    let readings = document
+        .select(&label_selector)
+        .map(|label| {
+            let section = section(label);
+            let value = section
+                .iter()
+                .flat_map(|cell| cell.descendants().filter_map(ElementRef::wrap))
+                .find(|e| e.value().classes().any(|c| c == READING_CLASS))
+                .map(|reading| text(&reading));
+            let reading = Reading {
+                value,
+                text: section.iter().map(text).collect::<Vec<_>>().join(" "),
+            };
+            (text(&label).trim().to_owned(), reading)
+        })
+        .collect::<Vec<_>>();
+    if readings.is_empty() {
+        anyhow::bail!("No readings found");
+    }
+
+    let reading = |prefix: &str| {
+        readings
+            .iter()
+            .find(|(label, _)| label.starts_with(prefix))
+            .map(|(_, reading)| reading)
+    };
+    let value = |prefix: &str| reading(prefix)?.value.as_deref().and_then(parse_number);
+    let extreme = |prefix: &str, markers: &[&str]| number_after(&reading(prefix)?.text, markers);
+    let wind = reading("Viento").and_then(|wind| wind.value.as_deref());
+
+    // e.g. `Última actualización 18-10-2025 12:30 UTC`
+    let page_text = text(&document.root_element());
+    let update_time = page_text
+        .split_once("actualización")
+        .and_then(|(_, rest)| {
+            let (time, _) = rest.split_once("UTC")?;
+            NaiveDateTime::parse_from_str(time.trim(), "%d-%m-%Y %H:%M").ok()
+        })
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string());
+
+    Ok(Measurements {
+        update_time,
+        humidity: value("Humedad").map(|v| v.round() as u64),
+        precipitation: value("Precip"),
+        pressure: value("Presi").map(|v| v.round() as u64),
+        temperature: value("Temperatura"),
+        wind_direction: wind.and_then(parse_direction),
+        wind_speed: wind.and_then(parse_number).map(|v| v.round() as u64),
+        temperature_max: extreme("Temperatura", &["máx", "max"]),
+        temperature_min: extreme("Temperatura", &["mín", "min"]),
+        // stations upload the current wind and the daily maximum gust only (`WND` and `DGST`),
+        // so this maximum is that of the gusts and there is no current gust
+        gusts_speed_max: extreme("Viento", &["máx", "max"]).map(|v| v.round() as u64),
+        ..Default::default()
+    })
+}
 
-    let reading = reading
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("Failed to extract {}", name))?;
+async fn download_feed(region: &str) -> anyhow::Result<HashMap<String, Measurements>> {
+    let request = Request::builder()
+        .method(Method::Get)
+        .uri(format!("{}feed/xml/{}", BASE_URL, region))
+        .build();
 
-    Ok(reading.as_str().to_string())
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected response status: {}", response.status());
+    }
+    let (body, _, _) = encoding_rs::ISO_8859_15.decode(response.body());
+    Ok(parse_feed(&body))
 }
 
 impl Downloader for MeteoclimaticDownloader {
+    fn name(&self) -> &'static str {
+        "meteoclimatic"
+    }
+
     fn base_url(&self) -> String {
         BASE_URL.to_owned()
     }
 
-    async fn try_download(&self, url: &str) -> anyhow::Result<Measurements> {
-        let request = Request::builder()
-            .method(Method::Get)
-            //.header("Accept-Charset", "utf-8") // server ignores this anyway?
-            .uri(url)
-            .build();
+    fn station_url(&self, id: &str) -> String {
+        format!("{}perfil/{}", BASE_URL, id)
+    }
 
-        let response: Response = spin_sdk::http::send(request).await?;
-        //let body = String::from_utf8_lossy(response.body());
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "pressure",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "temperature_max",
+            "temperature_min",
+            "gusts_speed_max",
+        ]
+    }
 
-        // encoding reported in: <meta http-equiv="Content-Type" content="text/html; charset=
-        let (body, _, _) = encoding_rs::ISO_8859_15.decode(response.body());
+    // stations of the same region are read from the regional feed,
+    // the remaining ones and those missing in the feed from their pages
+    async fn download_batch(
+        &self,
+        urls: &[&str],
+        fields: &Fields,
+    ) -> Vec<anyhow::Result<Measurements>> {
+        let ids = urls
+            .iter()
+            .map(|url| station_id(url).map(|id| id.to_uppercase()))
+            .collect::<Vec<_>>();
 
-        let re = Regex::new(r#"class="titolet" *>(?<title>[^<]+)"#)?;
-        let titles: Vec<String> = re
-            .captures_iter(&body)
-            .filter_map(|caps| {
-                caps.name("title")
-                    .map(|m| m.as_str().to_string().trim().to_string())
-            })
-            .collect();
-
-        let re = Regex::new(r#"class="dadesactuals" *>(?<reading>[^<]+)"#)?;
-        let readings: Vec<String> = re
-            .captures_iter(&body)
-            .filter_map(|caps| {
-                caps.name("reading")
-                    .map(|m| m.as_str().to_string().trim().to_string())
-            })
-            .collect();
-
-        let dict: HashMap<_, _> = titles.into_iter().zip(readings.into_iter()).collect();
-
-        let temperature = match dict.get("Temperatura") {
-            Some(val) => Some(parse_reading(val, "ºC", "temperature")?.parse::<f64>()?),
-            None => None,
-        };
-
-        let humidity = match dict.get("Humedad") {
-            Some(val) => Some(parse_reading(val, "%", "humidity")?.parse::<u64>()?),
-            None => None,
-        };
-
-        let pressure = match dict.get("Presión") {
-            Some(val) => Some(parse_reading(val, "hPa", "pressure")?.parse::<u64>()?),
-            None => None,
-        };
-
-        let precipitation = match dict.get("Precip.") {
-            Some(val) => Some(parse_reading(val, "mm", "precipitation")?.parse::<f64>()?),
-            None => None,
-        };
-
-        let (wind_direction, wind_speed) = match dict.get("Viento") {
-            Some(val) => {
-                let re = Regex::new(r#"(.+)&nbsp;&nbsp;(.+)"#)?;
-                let wind = re
-                    .captures(val.as_str())
-                    .ok_or_else(|| anyhow::anyhow!("Failed to split wind"))?;
-                let direction = wind
-                    .get(1)
-                    .ok_or_else(|| anyhow::anyhow!("Failed to extract wind direction"))?
-                    .as_str()
-                    .trim()
-                    .replace("O", "W");
-                let speed = wind
-                    .get(2)
-                    .ok_or_else(|| anyhow::anyhow!("Failed to extract wind speed"))?
-                    .as_str()
-                    .trim();
-
-                let speed = parse_reading(speed, "km/h", "wind_speed")?.parse::<f64>()?;
-                let speed_int = speed.round() as u64;
-
-                (Some(direction), Some(speed_int))
+        let mut regions: HashMap<&str, usize> = HashMap::new();
+        for id in ids.iter().flatten() {
+            if let Some(region) = region(id) {
+                *regions.entry(region).or_default() += 1;
             }
-            None => (None, None),
-        };
-
-        let re = Regex::new(r#"Última actualización ?(\d\d-\d\d-\d\d\d\d \d\d:\d\d) ?UTC</td>"#)
-            .unwrap();
-        let delimiter = '-';
-        let update_time = match re.captures(&body) {
-            Some(captures) => match captures[1].split_once(' ') {
-                Some((date, time)) => {
-                    let date = date
-                        .to_string()
-                        .as_str()
-                        .split(delimiter)
-                        .rev()
-                        .collect::<Vec<_>>()
-                        .join(&delimiter.to_string());
-                    Some(format!("{} {}", date, time))
+        }
+
+        let mut feeds = HashMap::new();
+        for (region, count) in regions {
+            if count < MIN_BATCH_SIZE || !self.take_upstream(region) {
+                continue;
+            }
+            let started = Instant::now();
+            match download_feed(region).await {
+                Ok(feed) => {
+                    feeds.insert(region, (feed, started.elapsed()));
                 }
-                None => None,
-            },
-            None => None,
-        };
-
-        Ok(Measurements {
-            update_time,
-            humidity,
-            precipitation,
-            pressure,
-            temperature,
-            wind_direction,
-            wind_speed,
-            gusts_speed: None,
-        })
+                Err(e) => log::warn!("{} while downloading feed of: {}", e, region),
+            }
+        }
+
+        let results = urls
+            .iter()
+            .zip(ids.iter())
+            .map(|(url, id)| {
+                let id = id.as_deref()?;
+                let (feed, latency) = feeds.get(region(id)?)?;
+                let measurements = feed.get(id)?.clone();
+                Some(self.finish(url, *latency, Ok(measurements)))
+            })
+            .collect::<Vec<_>>();
+
+        self.download_remaining(urls, results, fields).await
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let request = Request::builder().method(Method::Get).uri(url).build();
+
+        let response: Response = spin_sdk::http::send(request).await?;
+        if *response.status() != 200 {
+            anyhow::bail!("Unexpected response status: {}", response.status());
+        }
+        parse_page(response.body())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // readings side by side, each label heading its column, UTF-8 declared by `<meta charset>`
+    const PAGE_CHARSET: &[u8] = include_bytes!("fixtures/meteoclimatic_charset.html");
+    // readings one below the other, each label alone in its row, ISO-8859-1 declared by
+    // `<meta http-equiv>`, and a station without a barometer
+    const PAGE_HTTP_EQUIV: &[u8] = include_bytes!("fixtures/meteoclimatic_http_equiv.html");
+    // regional feed listing the station of `PAGE_CHARSET` among others
+    const FEED: &[u8] = include_bytes!("fixtures/meteoclimatic_feed.xml");
+
+    fn feed() -> HashMap<String, Measurements> {
+        let (xml, _, _) = encoding_rs::ISO_8859_15.decode(FEED);
+        parse_feed(&xml)
+    }
+
+    #[test]
+    fn page_with_columns() {
+        let m = parse_page(PAGE_CHARSET).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:30"));
+        assert_eq!(m.temperature, Some(21.4));
+        assert_eq!(m.temperature_max, Some(24.8));
+        assert_eq!(m.temperature_min, Some(14.3));
+        assert_eq!(m.humidity, Some(58));
+        assert_eq!(m.pressure, Some(1016));
+        assert_eq!(m.wind_direction.as_deref(), Some("SW"));
+        assert_eq!(m.wind_speed, Some(14));
+        assert_eq!(m.gusts_speed_max, Some(38));
+        assert_eq!(m.gusts_speed, None);
+        assert_eq!(m.precipitation, Some(0.4));
+    }
+
+    #[test]
+    fn page_with_rows_and_missing_cell() {
+        let m = parse_page(PAGE_HTTP_EQUIV).unwrap();
+        // found only when the page is decoded as declared
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:20"));
+        assert_eq!(m.temperature_max, Some(12.1));
+        assert_eq!(m.temperature_min, Some(3.9));
+
+        assert_eq!(m.temperature, Some(8.7));
+        assert_eq!(m.humidity, Some(91));
+        // the empty cell leaves the readings below in place
+        assert_eq!(m.pressure, None);
+        assert_eq!(m.wind_direction.as_deref(), Some("N"));
+        assert_eq!(m.wind_speed, Some(6));
+        assert_eq!(m.gusts_speed_max, Some(21));
+        assert_eq!(m.precipitation, Some(2.2));
+    }
+
+    #[test]
+    fn declared_encodings() {
+        assert_eq!(declared_encoding(PAGE_CHARSET).unwrap(), encoding_rs::UTF_8);
+        assert_eq!(
+            declared_encoding(PAGE_HTTP_EQUIV).unwrap(),
+            Encoding::for_label(b"iso-8859-1").unwrap()
+        );
+        assert_eq!(
+            declared_encoding(b"<html><body></body></html>").unwrap(),
+            encoding_rs::ISO_8859_15
+        );
+    }
+
+    #[test]
+    fn page_without_readings() {
+        assert!(parse_page(b"<html><body><p>Estacion no encontrada</p></body></html>").is_err());
+    }
+
+    #[test]
+    fn feed_stations() {
+        let stations = feed();
+        assert_eq!(stations.len(), 2);
+
+        let m = &stations["ESCAT0800000008650B"];
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:25"));
+        assert_eq!(m.temperature, Some(20.9));
+        assert_eq!(m.humidity, Some(61));
+        // no barometer at this station
+        assert_eq!(m.pressure, None);
+        assert_eq!(m.wind_direction.as_deref(), Some("SSW"));
+        assert_eq!(m.wind_speed, Some(9));
+        assert_eq!(m.gusts_speed_max, Some(31));
+        assert_eq!(m.precipitation, Some(0.2));
+    }
+
+    #[test]
+    fn feed_matches_page() {
+        let stations = feed();
+        let from_feed = &stations["ESCAT0800000008241A"];
+        let from_page = parse_page(PAGE_CHARSET).unwrap();
+        // the maximum of the wind in the feed is the maximum gust shown on the page
+        assert_eq!(from_feed.gusts_speed_max, from_page.gusts_speed_max);
+        assert_eq!(from_feed.update_time, from_page.update_time);
+        assert_eq!(from_feed.temperature, from_page.temperature);
+        assert_eq!(from_feed.temperature_max, from_page.temperature_max);
+        assert_eq!(from_feed.temperature_min, from_page.temperature_min);
+        assert_eq!(from_feed.humidity, from_page.humidity);
+        assert_eq!(from_feed.pressure, from_page.pressure);
+        assert_eq!(from_feed.wind_direction, from_page.wind_direction);
+        assert_eq!(from_feed.wind_speed, from_page.wind_speed);
+        assert_eq!(from_feed.precipitation, from_page.precipitation);
     }
 }
@@ -0,0 +1,305 @@
+use crate::collectors::Downloader;
+use crate::measurements::{forecast_key, Fields, Measurements, FORECAST_HOURS};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use spin_sdk::http::{Method, Request, Response};
+
+pub const BASE_URL: &str = "https://api.met.no/weatherapi/locationforecast/";
+const API_URL: &str = "https://api.met.no/weatherapi/locationforecast/2.0/complete";
+
+// MET Norway blocks clients that do not identify themselves
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " github.com/gergelyk/weather-data-provider"
+);
+
+// steps of the series are hourly for the first days
+const MAX_TIME_OFFSET_MINUTES: i64 = 30;
+// hours of the series kept beyond the last forecast key, longer than the forecast is cached
+const SERIES_MARGIN_HOURS: i64 = 2;
+// steps with absolute times, so that `+<hours>h` is relative to the request, not the fetch
+const FORECAST_SERIES_KEY: &str = "forecast_series";
+const KMH_PER_MS: f64 = 3.6;
+
+pub struct MetNoDownloader {}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRaw {
+    geometry: ForecastRawGeometry,
+    properties: ForecastRawProperties,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRawGeometry {
+    /// longitude, latitude and altitude
+    coordinates: Vec<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRawProperties {
+    meta: ForecastRawMeta,
+    timeseries: Vec<ForecastRawStep>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRawMeta {
+    updated_at: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRawStep {
+    time: String,
+    data: ForecastRawData,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRawData {
+    instant: ForecastRawInstant,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastRawInstant {
+    details: ForecastRawDetails,
+}
+
+// gusts are provided by the `complete` variant only
+#[derive(Deserialize, Debug)]
+struct ForecastRawDetails {
+    air_temperature: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_speed_of_gust: Option<f64>,
+}
+
+/// Step of the forecast series kept in the cache, speeds in km/h
+#[derive(Serialize, Deserialize, Debug)]
+struct ForecastStep {
+    /// Unix timestamp
+    time: i64,
+    wind_speed: Option<u64>,
+    gusts_speed: Option<u64>,
+    temperature: Option<f64>,
+}
+
+/// Forecast of a Locationforecast 2.0 response, as a series of steps around `now`.
+///
+/// The series is kept under `FORECAST_SERIES_KEY`, the forecast keys are given by
+/// `resolve_forecast` when the readings are served. Other providers serving the same
+/// GeoJSON structure can be parsed with it too.
+pub fn parse_forecast(body: &str, now: DateTime<Utc>) -> anyhow::Result<Measurements> {
+    let raw: ForecastRaw = serde_json::from_str(body).context("Forecast parsing failed")?;
+
+    let updated_at: DateTime<Utc> = raw.properties.meta.updated_at.parse()?;
+    // the forecast is served from the cache for a while, the horizon must still be covered
+    let max_hours = FORECAST_HOURS.iter().max().copied().unwrap_or_default();
+    let first = now - Duration::hours(1);
+    let last = now + Duration::hours(max_hours + SERIES_MARGIN_HOURS);
+
+    let kmh = |speed: f64| (speed * KMH_PER_MS).round() as u64;
+    let mut series = Vec::new();
+    for step in raw.properties.timeseries {
+        let time = step.time.parse::<DateTime<Utc>>()?;
+        if time < first || time > last {
+            continue;
+        }
+        let details = step.data.instant.details;
+        series.push(ForecastStep {
+            time: time.timestamp(),
+            wind_speed: details.wind_speed.map(kmh),
+            gusts_speed: details.wind_speed_of_gust.map(kmh),
+            temperature: details.air_temperature,
+        });
+    }
+
+    let mut measurements = Measurements {
+        update_time: Some(updated_at.format("%Y-%m-%d %H:%M").to_string()),
+        latitude: raw.geometry.coordinates.get(1).copied(),
+        longitude: raw.geometry.coordinates.first().copied(),
+        ..Default::default()
+    };
+    measurements.extra.insert(
+        FORECAST_SERIES_KEY.to_owned(),
+        serde_json::to_value(series)?,
+    );
+    Ok(measurements)
+}
+
+/// Replaces the forecast series by the forecast keys for the hours ahead of `now`.
+pub fn resolve_forecast(measurements: &mut Measurements, now: DateTime<Utc>) {
+    let Some(series) = measurements.extra.remove(FORECAST_SERIES_KEY) else {
+        return;
+    };
+    let series: Vec<ForecastStep> = match serde_json::from_value(series) {
+        Ok(series) => series,
+        Err(e) => {
+            log::error!("{} while reading forecast series", e);
+            return;
+        }
+    };
+
+    for &hours in FORECAST_HOURS {
+        let target = (now + Duration::hours(hours)).timestamp();
+        let Some(step) = series.iter().min_by_key(|step| (step.time - target).abs()) else {
+            break;
+        };
+        if (step.time - target).abs() > MAX_TIME_OFFSET_MINUTES * 60 {
+            continue;
+        }
+
+        let values = [
+            ("wind_speed", step.wind_speed.map(|s| json!(s))),
+            ("gusts_speed", step.gusts_speed.map(|s| json!(s))),
+            ("temperature", step.temperature.map(|t| json!(t))),
+        ];
+        for (quantity, value) in values {
+            if let Some(value) = value {
+                measurements
+                    .extra
+                    .insert(forecast_key(quantity, hours), value);
+            }
+        }
+    }
+}
+
+impl Downloader for MetNoDownloader {
+    fn name(&self) -> &'static str {
+        "metno"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_owned()
+    }
+
+    /// `id` are the coordinates, e.g. `41.7935,1.8203`
+    fn station_url(&self, id: &str) -> String {
+        let (latitude, longitude) = id.split_once(',').unwrap_or((id, ""));
+        format!(
+            "{}?lat={}&lon={}",
+            API_URL,
+            latitude.trim(),
+            longitude.trim()
+        )
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "latitude",
+            "longitude",
+            "forecast_wind_speed_+1h",
+            "forecast_wind_speed_+3h",
+            "forecast_wind_speed_+6h",
+            "forecast_gusts_speed_+1h",
+            "forecast_gusts_speed_+3h",
+            "forecast_gusts_speed_+6h",
+            "forecast_temperature_+1h",
+            "forecast_temperature_+3h",
+            "forecast_temperature_+6h",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        // new runs are published hourly at most
+        60
+    }
+
+    fn is_forecast(&self) -> bool {
+        true
+    }
+
+    fn resolve(&self, measurements: &mut Measurements) {
+        resolve_forecast(measurements, Utc::now());
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let url = url::Url::parse(url)?;
+        let coordinate = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .and_then(|(_, value)| value.parse::<f64>().ok())
+                .ok_or_else(|| anyhow!("Invalid coordinates: {}", url))
+        };
+        // more than 4 decimals are rejected and would defeat caching upstream
+        let url = format!(
+            "{}?lat={:.4}&lon={:.4}",
+            API_URL,
+            coordinate("lat")?,
+            coordinate("lon")?
+        );
+
+        let request = Request::builder()
+            .method(Method::Get)
+            .header("User-Agent", USER_AGENT)
+            .uri(url)
+            .build();
+
+        let response: Response = spin_sdk::http::send(request).await?;
+        if *response.status() != 200 {
+            anyhow::bail!("Unexpected response status: {}", response.status());
+        }
+        let body = String::from_utf8_lossy(response.body());
+        parse_forecast(&body, Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("fixtures/metno_complete.json");
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn forecast(fetched_at: &str, served_at: &str) -> Measurements {
+        let mut measurements = parse_forecast(FIXTURE, at(fetched_at)).unwrap();
+        resolve_forecast(&mut measurements, at(served_at));
+        measurements
+    }
+
+    #[test]
+    fn forecast_keys_in_km_h() {
+        let m = forecast("2025-06-14T10:05:00Z", "2025-06-14T10:05:00Z");
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 09:47"));
+        assert_eq!(m.latitude, Some(41.7935));
+        assert_eq!(m.longitude, Some(1.8203));
+        assert!(!m.extra.contains_key(FORECAST_SERIES_KEY));
+
+        assert_eq!(m.extra["forecast_wind_speed_+1h"], json!(10));
+        assert_eq!(m.extra["forecast_gusts_speed_+1h"], json!(19));
+        assert_eq!(m.extra["forecast_temperature_+1h"], json!(15.1));
+        assert_eq!(m.extra["forecast_wind_speed_+3h"], json!(16));
+        assert_eq!(m.extra["forecast_gusts_speed_+3h"], json!(29));
+        assert_eq!(m.extra["forecast_temperature_+3h"], json!(17.0));
+        assert_eq!(m.extra["forecast_wind_speed_+6h"], json!(23));
+        assert_eq!(m.extra["forecast_gusts_speed_+6h"], json!(43));
+        assert_eq!(m.extra["forecast_temperature_+6h"], json!(16.2));
+    }
+
+    #[test]
+    fn offsets_relative_to_serving_time() {
+        // served from the cache 50 minutes after the fetch, +1h is noon now
+        let m = forecast("2025-06-14T10:05:00Z", "2025-06-14T10:55:00Z");
+        assert_eq!(m.extra["forecast_wind_speed_+1h"], json!(13));
+        assert_eq!(m.extra["forecast_temperature_+1h"], json!(16.3));
+        assert_eq!(m.extra["forecast_wind_speed_+6h"], json!(21));
+    }
+
+    #[test]
+    fn keys_beyond_series_left_out() {
+        let m = forecast("2025-06-14T14:00:00Z", "2025-06-14T16:00:00Z");
+        assert_eq!(m.extra["forecast_wind_speed_+1h"], json!(21));
+        assert_eq!(m.extra["forecast_wind_speed_+3h"], json!(11));
+        assert!(!m.extra.contains_key("forecast_wind_speed_+6h"));
+        assert!(!m.extra.contains_key("forecast_temperature_+6h"));
+    }
+
+    #[test]
+    fn invalid_body() {
+        assert!(parse_forecast("{}", at("2025-06-14T10:05:00Z")).is_err());
+    }
+}
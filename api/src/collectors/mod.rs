@@ -1,14 +1,29 @@
 pub mod common;
+pub mod units;
 pub mod aemet;
 pub mod meteocat;
 pub mod meteoclimatic;
 pub mod weatherlink;
+pub mod weatherlink_v2;
 pub mod openwindmap;
+pub mod metno;
+pub mod holfuy;
+pub mod windguru;
+pub mod wunderground;
+pub mod ambient;
+pub mod local;
 
 pub use aemet::AemetDownloader;
 pub use meteocat::MeteocatDownloader;
 pub use meteoclimatic::MeteoclimaticDownloader;
 pub use weatherlink::WeatherlinkDownloader;
+pub use weatherlink_v2::WeatherlinkV2Downloader;
 pub use openwindmap::OpenWindMapDownloader;
+pub use metno::MetNoDownloader;
+pub use holfuy::HolfuyDownloader;
+pub use windguru::WindguruDownloader;
+pub use wunderground::WundergroundDownloader;
+pub use ambient::AmbientDownloader;
+pub use local::LocalDownloader;
 
 pub use common::Downloader;
\ No newline at end of file
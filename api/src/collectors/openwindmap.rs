@@ -1,14 +1,20 @@
-use crate::measurements::Measurements;
+use crate::measurements::{wind_windows, window_key, Fields, Measurements};
 use anyhow::anyhow;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use spin_sdk::http::{Method, Request, Response};
 use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use crate::collectors::common::wind_direction_name;
 use crate::collectors::Downloader;
 
-const API_URL: &str = "http://api.pioupiou.fr/v1/live/";
+const API_URL: &str = "https://api.pioupiou.fr/v1/live/";
+const ARCHIVE_URL: &str = "https://api.pioupiou.fr/v1/archive/";
 pub const BASE_URL: &str = "https://www.openwindmap.org/";
 
+// readings are sent every 4 minutes, a few of them may get lost
+const MAX_READINGS_AGE_MINUTES: i64 = 20;
+
 pub struct OpenWindMapDownloader {}
 
 #[derive(Deserialize, Debug)]
@@ -18,25 +24,184 @@ struct MeasurementsRaw {
 
 #[derive(Deserialize, Debug)]
 struct MeasurementsRawData {
+    location: MeasurementsRawLocation,
     measurements: MeasurementsRawMeasurements,
+    status: Option<MeasurementsRawStatus>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MeasurementsRawLocation {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
 }
 
+// wind readings are missing while the station is offline
 #[derive(Deserialize, Debug)]
 struct MeasurementsRawMeasurements {
     date: String,
-    wind_heading: f64,
-    wind_speed_avg: f64,
-    wind_speed_max: f64,
+    wind_heading: Option<f64>,
+    wind_speed_avg: Option<f64>,
+    wind_speed_max: Option<f64>,
+    wind_speed_min: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MeasurementsRawStatus {
+    /// `on` or `off`
+    state: Option<String>,
+}
+
+/// Readings of a station given the response of the live API, `now` tells outdated ones.
+pub fn parse_live(body: &str, now: DateTime<Utc>) -> anyhow::Result<Measurements> {
+    let measurement_raw: MeasurementsRaw = serde_json::from_str(body)?;
+    let data = measurement_raw.data;
+    let readings = data.measurements;
+
+    let update_time: DateTime<Utc> = readings.date.parse()?;
+    let online = data
+        .status
+        .and_then(|status| status.state)
+        .map_or(true, |state| state == "on");
+    let status = if !online {
+        "offline"
+    } else if now - update_time > Duration::minutes(MAX_READINGS_AGE_MINUTES) {
+        "outdated"
+    } else {
+        "online"
+    };
+
+    let measurements = Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        wind_direction: readings.wind_heading.map(|h| wind_direction_name(h).to_owned()),
+        wind_speed: readings.wind_speed_avg.map(|s| s.round() as u64),
+        gusts_speed: readings.wind_speed_max.map(|s| s.round() as u64),
+        latitude: data.location.latitude,
+        longitude: data.location.longitude,
+        wind_speed_min: readings.wind_speed_min.map(|s| s.round() as u64),
+        status: Some(status.to_owned()),
+        ..Default::default()
+    };
+
+    Ok(measurements)
+}
+
+// rows of the archive are described by the legend, e.g. `["time", "latitude", ...]`
+#[derive(Deserialize, Debug)]
+struct ArchiveRaw {
+    legend: Vec<String>,
+    data: Vec<Vec<Value>>,
+}
+
+/// Minimum, average and maximum wind over the windows ending at `now`, given an archive response.
+pub fn parse_archive(
+    body: &str,
+    now: DateTime<Utc>,
+    windows: &[i64],
+) -> anyhow::Result<BTreeMap<String, Value>> {
+    let archive: ArchiveRaw = serde_json::from_str(body)?;
+    let column = |name: &str| {
+        archive
+            .legend
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| anyhow!("Column not found: {}", name))
+    };
+    let (time, min, avg, max) = (
+        column("time")?,
+        column("wind_speed_min")?,
+        column("wind_speed_avg")?,
+        column("wind_speed_max")?,
+    );
+
+    let rows = archive
+        .data
+        .iter()
+        .filter_map(|row| {
+            let time = row.get(time)?.as_str()?.parse::<DateTime<Utc>>().ok()?;
+            let value = |i: usize| row.get(i).and_then(|v| v.as_f64());
+            Some((time, value(min)?, value(avg)?, value(max)?))
+        })
+        .collect::<Vec<_>>();
+
+    let mut statistics = BTreeMap::new();
+    for &minutes in windows {
+        let since = now - Duration::minutes(minutes);
+        let recent = rows
+            .iter()
+            .filter(|(time, ..)| *time > since)
+            .collect::<Vec<_>>();
+        // empty windows are left out, as any missing reading
+        if recent.is_empty() {
+            continue;
+        }
+        let lulls = recent.iter().map(|r| r.1).fold(f64::INFINITY, f64::min);
+        let average = recent.iter().map(|r| r.2).sum::<f64>() / recent.len() as f64;
+        let gusts = recent.iter().map(|r| r.3).fold(f64::NEG_INFINITY, f64::max);
+        for (stat, speed) in [("min", lulls), ("avg", average), ("max", gusts)] {
+            statistics.insert(window_key(stat, minutes), json!(speed.round() as u64));
+        }
+    }
+    Ok(statistics)
+}
+
+async fn download_archive(vendor_id: &str) -> anyhow::Result<BTreeMap<String, Value>> {
+    let windows = wind_windows();
+    let now = Utc::now();
+    let longest = windows.iter().max().copied().unwrap_or_default();
+    let start = now - Duration::minutes(longest);
+    let url = format!(
+        "{}{}?start={}&stop=now",
+        ARCHIVE_URL,
+        vendor_id,
+        start.format("%Y-%m-%dT%H:%M:%SZ")
+    );
+
+    let request = Request::builder().method(Method::Get).uri(url).build();
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected response status: {}", response.status());
+    }
+    parse_archive(&String::from_utf8_lossy(response.body()), now, &windows)
 }
 
 impl Downloader for OpenWindMapDownloader {
+    fn name(&self) -> &'static str {
+        "openwindmap"
+    }
+
     fn base_url(&self) -> String {
         BASE_URL.to_owned()
     }
 
-    async fn try_download(&self, url: &str) -> anyhow::Result<Measurements> {
+    fn station_url(&self, id: &str) -> String {
+        format!("{}windbird-{}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "wind_speed_min",
+            "status",
+            "latitude",
+            "longitude",
+        ]
+    }
+
+    fn provides(&self, fields: &Fields) -> bool {
+        fields.contains_any(self.provided_fields()) || fields.requests_windows()
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        // windbirds report every 4 minutes
+        4
+    }
+
+    async fn try_download(&self, url: &str, fields: &Fields) -> anyhow::Result<Measurements> {
 
-        let path = url.strip_prefix(&BASE_URL)
+        let path = url.strip_prefix(BASE_URL)
             .ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
 
         let vendor_id = path.split('-')
@@ -51,22 +216,116 @@ impl Downloader for OpenWindMapDownloader {
             .build();
 
         let response: Response = spin_sdk::http::send(request).await?;
+        if *response.status() != 200 {
+            anyhow::bail!("Unexpected response status: {}", response.status());
+        }
         let body = String::from_utf8_lossy(response.body());
-        let measurement_raw: MeasurementsRaw = serde_json::from_str(&body)?;
+        let mut measurements = parse_live(&body, Utc::now())?;
 
-        let update_time: DateTime<Utc> = measurement_raw.data.measurements.date.parse()?;
+        // the archive costs another request, so it is fetched only when asked for
+        if fields.requests_windows() {
+            match download_archive(vendor_id).await {
+                Ok(statistics) => measurements.extra.extend(statistics),
+                Err(e) => log::warn!("{} while downloading archive of: {}", e, vendor_id),
+            }
+        }
+        Ok(measurements)
+    }
+}
 
-        let measurements = Measurements {
-            update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
-            humidity: None,
-            precipitation: None,
-            pressure: None,
-            temperature: None,
-            wind_direction: Some(wind_direction_name(measurement_raw.data.measurements.wind_heading).to_owned()),
-            wind_speed: Some(measurement_raw.data.measurements.wind_speed_avg.round() as u64),
-            gusts_speed: Some(measurement_raw.data.measurements.wind_speed_max.round() as u64),
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(measurements)
+    // readings every 4 minutes from 10:00 to 10:56, the average of 10:20 is missing
+    const ARCHIVE: &str = include_str!("fixtures/openwindmap_archive.json");
+    // readings of 10:52, and of a station switched off a while ago
+    const LIVE: &str = include_str!("fixtures/openwindmap_live.json");
+    const LIVE_OFFLINE: &str = include_str!("fixtures/openwindmap_live_offline.json");
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn fresh_readings() {
+        let m = parse_live(LIVE, at("2025-06-14T10:58:00Z")).unwrap();
+        assert_eq!(m.status.as_deref(), Some("online"));
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:52"));
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        assert_eq!(m.wind_speed, Some(19));
+        assert_eq!(m.gusts_speed, Some(29));
+        assert_eq!(m.wind_speed_min, Some(11));
+        assert_eq!(m.latitude, Some(45.81432));
+        assert_eq!(m.longitude, Some(6.24287));
+    }
+
+    #[test]
+    fn outdated_readings() {
+        // 20 minutes old at most
+        let m = parse_live(LIVE, at("2025-06-14T11:12:11Z")).unwrap();
+        assert_eq!(m.status.as_deref(), Some("online"));
+        let m = parse_live(LIVE, at("2025-06-14T11:13:00Z")).unwrap();
+        assert_eq!(m.status.as_deref(), Some("outdated"));
+        // the last readings are still served
+        assert_eq!(m.wind_speed, Some(19));
+    }
+
+    #[test]
+    fn offline_station() {
+        let m = parse_live(LIVE_OFFLINE, at("2025-06-14T10:58:00Z")).unwrap();
+        // offline takes precedence over outdated
+        assert_eq!(m.status.as_deref(), Some("offline"));
+        assert_eq!(m.update_time.as_deref(), Some("2025-05-03 08:14"));
+        assert_eq!(m.wind_direction, None);
+        assert_eq!(m.wind_speed, None);
+        assert_eq!(m.gusts_speed, None);
+        assert_eq!(m.latitude, Some(45.87412));
+    }
+
+    #[test]
+    fn window_statistics() {
+        let stats = parse_archive(ARCHIVE, at("2025-06-14T10:58:00Z"), &[10, 30, 60]).unwrap();
+        let expected = [
+            ("wind_speed_min_10m", 13),
+            ("wind_speed_avg_10m", 19),
+            ("wind_speed_max_10m", 29),
+            ("wind_speed_min_30m", 7),
+            ("wind_speed_avg_30m", 16),
+            ("wind_speed_max_30m", 29),
+            ("wind_speed_min_60m", 4),
+            ("wind_speed_avg_60m", 13),
+            ("wind_speed_max_60m", 29),
+        ];
+        assert_eq!(stats.len(), expected.len());
+        for (key, speed) in expected {
+            assert_eq!(stats[key], json!(speed), "{}", key);
+        }
+    }
+
+    #[test]
+    fn window_edges() {
+        // the reading of 10:48 is at the start of the window, so it is left out
+        let stats = parse_archive(ARCHIVE, at("2025-06-14T10:58:00Z"), &[10]).unwrap();
+        assert_eq!(stats["wind_speed_min_10m"], json!(13));
+        // a minute earlier the window starts at 10:47, so the reading of 10:48 is in
+        let stats = parse_archive(ARCHIVE, at("2025-06-14T10:57:00Z"), &[10]).unwrap();
+        assert_eq!(stats["wind_speed_min_10m"], json!(10));
+    }
+
+    #[test]
+    fn empty_window() {
+        let stats = parse_archive(ARCHIVE, at("2025-06-14T10:58:00Z"), &[1, 10]).unwrap();
+        assert!(!stats.contains_key("wind_speed_avg_1m"));
+        assert!(stats.contains_key("wind_speed_avg_10m"));
+
+        let stats = parse_archive(ARCHIVE, at("2025-06-14T12:00:00Z"), &[10, 30]).unwrap();
+        assert!(stats.is_empty());
+    }
+
+    #[test]
+    fn missing_column() {
+        let body = r#"{"legend": ["time", "wind_speed_avg"], "data": []}"#;
+        assert!(parse_archive(body, at("2025-06-14T10:58:00Z"), &[10]).is_err());
     }
 }
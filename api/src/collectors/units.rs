@@ -0,0 +1,112 @@
+// Conversion of readings into the units of `Measurements`. Units are given as reported
+// by the provider, e.g. `&deg;F`, `mph` or `inHg`, `None` means the unit is not recognized.
+
+const KMH_PER_MS: f64 = 3.6;
+const KMH_PER_MPH: f64 = 1.609344;
+const KMH_PER_KNOT: f64 = 1.852;
+const HPA_PER_INHG: f64 = 33.863886;
+const HPA_PER_MMHG: f64 = 1.333224;
+const MM_PER_INCH: f64 = 25.4;
+
+fn normalize(unit: &str) -> String {
+    unit.replace("&deg;", "")
+        .replace('°', "")
+        .trim()
+        .to_lowercase()
+}
+
+/// Temperature in °C
+pub fn temperature(value: f64, unit: &str) -> Option<f64> {
+    match normalize(unit).as_str() {
+        "c" => Some(value),
+        "f" => Some((value - 32.0) * 5.0 / 9.0),
+        "k" => Some(value - 273.15),
+        _ => None,
+    }
+}
+
+/// Speed in km/h
+pub fn speed(value: f64, unit: &str) -> Option<f64> {
+    match normalize(unit).as_str() {
+        "km/h" | "kmh" | "kph" => Some(value),
+        "m/s" | "ms" => Some(value * KMH_PER_MS),
+        "mph" => Some(value * KMH_PER_MPH),
+        "kn" | "kt" | "kts" | "knots" => Some(value * KMH_PER_KNOT),
+        _ => None,
+    }
+}
+
+/// Pressure in hPa, a bare `in` is taken as inches of mercury
+pub fn pressure(value: f64, unit: &str) -> Option<f64> {
+    match normalize(unit).as_str() {
+        "hpa" | "mb" | "mbar" => Some(value),
+        "kpa" => Some(value * 10.0),
+        "inhg" | "in" => Some(value * HPA_PER_INHG),
+        "mmhg" => Some(value * HPA_PER_MMHG),
+        _ => None,
+    }
+}
+
+/// Precipitation in mm
+pub fn precipitation(value: f64, unit: &str) -> Option<f64> {
+    match normalize(unit).as_str() {
+        "mm" => Some(value),
+        "cm" => Some(value * 10.0),
+        "in" => Some(value * MM_PER_INCH),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("unit not recognized");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn temperatures() {
+        assert_close(temperature(68.0, "F"), 20.0);
+        assert_close(temperature(32.0, "°F"), 0.0);
+        assert_close(temperature(-40.0, "&deg;F"), -40.0);
+        assert_close(temperature(21.3, " &deg;C "), 21.3);
+        assert_close(temperature(293.15, "K"), 20.0);
+        assert_eq!(temperature(68.0, "R"), None);
+    }
+
+    #[test]
+    fn speeds() {
+        assert_close(speed(10.0, "mph"), 16.09);
+        assert_close(speed(10.0, "MPH"), 16.09);
+        assert_close(speed(10.0, "kts"), 18.52);
+        assert_close(speed(10.0, "m/s"), 36.0);
+        assert_close(speed(10.0, "km/h"), 10.0);
+        assert_eq!(speed(10.0, "ft/s"), None);
+    }
+
+    #[test]
+    fn pressures() {
+        assert_close(pressure(29.92, "inHg"), 1013.21);
+        // a bare `in` is the inch of mercury too
+        assert_close(pressure(29.92, "in"), 1013.21);
+        assert_close(pressure(760.0, "mmHg"), 1013.25);
+        assert_close(pressure(101.3, "kPa"), 1013.0);
+        assert_close(pressure(1013.2, "mbar"), 1013.2);
+        assert_eq!(pressure(14.7, "psi"), None);
+    }
+
+    #[test]
+    fn precipitations() {
+        assert_close(precipitation(0.13, "in"), 3.3);
+        assert_close(precipitation(1.2, "cm"), 12.0);
+        assert_close(precipitation(3.3, "mm"), 3.3);
+        assert_eq!(precipitation(0.13, "inHg"), None);
+    }
+}
@@ -1,9 +1,10 @@
-use crate::measurements::Measurements;
+use crate::measurements::{Fields, Measurements};
 use anyhow::anyhow;
 use chrono::DateTime;
 use spin_sdk::http::{Method, Request, Response};
 use serde::Deserialize;
-use crate::collectors::common::wind_direction_name;
+use crate::collectors::common::{wind_direction_name, ALL_FIELDS};
+use crate::collectors::units;
 use crate::collectors::Downloader;
 
 pub const BASE_URL: &str = "https://www.weatherlink.com/";
@@ -28,11 +29,27 @@ struct MeasurementsRaw {
 }
 
 impl Downloader for WeatherlinkDownloader {
+    fn name(&self) -> &'static str {
+        "weatherlink"
+    }
+
     fn base_url(&self) -> String {
         BASE_URL.to_owned()
     }
 
-    async fn try_download(&self, url: &str) -> anyhow::Result<Measurements> {
+    fn station_url(&self, id: &str) -> String {
+        format!("{}embeddablePage/show/{}/wide", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        ALL_FIELDS
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        5
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
 
         let prefix = format!("{}embeddablePage/show/", BASE_URL);
         let path = url.strip_prefix(&prefix)
@@ -53,18 +70,21 @@ impl Downloader for WeatherlinkDownloader {
         let body = String::from_utf8_lossy(response.body());
         let measurement_raw: MeasurementsRaw = serde_json::from_str(&body)?;
 
-        if !["mb", "hPa"].contains(&measurement_raw.barometerUnits.as_str()) {
-            anyhow::bail!("Unsupported barometer units: {}", measurement_raw.barometerUnits);
-        }
-        if measurement_raw.windUnits != "km/h" {
-            anyhow::bail!("Unsupported wind units: {}", measurement_raw.windUnits);
-        }
-        if measurement_raw.rainUnits != "mm" {
-            anyhow::bail!("Unsupported rain units: {}", measurement_raw.rainUnits);
-        }
-        if measurement_raw.tempUnits != "&deg;C" {
-            anyhow::bail!("Unsupported temperature units: {}", measurement_raw.tempUnits);
-        }
+        let barometer_units = measurement_raw.barometerUnits.as_str();
+        let wind_units = measurement_raw.windUnits.as_str();
+        let rain_units = measurement_raw.rainUnits.as_str();
+        let temp_units = measurement_raw.tempUnits.as_str();
+        let pressure = units::pressure(measurement_raw.barometer.parse()?, barometer_units)
+            .ok_or_else(|| anyhow!("Unsupported barometer units: {}", barometer_units))?;
+        let precipitation = units::precipitation(measurement_raw.rain.parse()?, rain_units)
+            .ok_or_else(|| anyhow!("Unsupported rain units: {}", rain_units))?;
+        let temperature = units::temperature(measurement_raw.temperature.parse()?, temp_units)
+            .ok_or_else(|| anyhow!("Unsupported temperature units: {}", temp_units))?;
+        let wind_speed = units::speed(measurement_raw.wind.parse()?, wind_units)
+            .ok_or_else(|| anyhow!("Unsupported wind units: {}", wind_units))?;
+        let gusts_speed = units::speed(measurement_raw.gust.parse()?, wind_units)
+            .ok_or_else(|| anyhow!("Unsupported wind units: {}", wind_units))?;
+
         let update_time = DateTime::from_timestamp(
             measurement_raw.lastReceived as i64 / 1000,
             (measurement_raw.lastReceived % 1000 * 1_000_000) as u32,
@@ -73,12 +93,13 @@ impl Downloader for WeatherlinkDownloader {
         let measurements = Measurements {
             update_time: update_time.map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
             humidity: Some(measurement_raw.humidity.parse()?),
-            precipitation: Some(measurement_raw.rain.parse()?),
-            pressure: Some(measurement_raw.barometer.parse::<f64>()?.round() as u64), // 1 mb = 1 hPa
-            temperature: Some(measurement_raw.temperature.parse()?),
+            precipitation: Some(precipitation),
+            pressure: Some(pressure.round() as u64),
+            temperature: Some(temperature),
             wind_direction: Some(wind_direction_name(measurement_raw.windDirection as f64).to_owned()),
-            wind_speed: Some(measurement_raw.wind.parse()?),
-            gusts_speed: Some(measurement_raw.gust.parse()?),
+            wind_speed: Some(wind_speed.round() as u64),
+            gusts_speed: Some(gusts_speed.round() as u64),
+            ..Default::default()
         };
 
         Ok(measurements)
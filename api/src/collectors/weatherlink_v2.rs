@@ -0,0 +1,243 @@
+use crate::collectors::common::wind_direction_name;
+use crate::collectors::units;
+use crate::collectors::Downloader;
+use crate::measurements::{Fields, Measurements};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use spin_sdk::http::{Method, Request, Response};
+use std::collections::HashMap;
+
+pub const BASE_URL: &str = "https://api.weatherlink.com/v2/";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Stations of a WeatherLink account, read from the official v2 API
+pub struct WeatherlinkV2Downloader {}
+
+#[derive(Deserialize, Debug)]
+struct CurrentRaw {
+    sensors: Vec<SensorRaw>,
+}
+
+// the keys of the readings depend on the data structure type of the sensor
+#[derive(Deserialize, Debug)]
+struct SensorRaw {
+    data: Vec<HashMap<String, Value>>,
+}
+
+/// Station ID given the URL of the current conditions, e.g. `123456`
+fn station_id(url: &str) -> Option<&str> {
+    let (_, id) = url.split_once("/v2/current/")?;
+    let id = id.split(['/', '?', '#']).next()?;
+    let valid_chars = id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    (!id.is_empty() && valid_chars).then_some(id)
+}
+
+/// HMAC of the parameters sorted by name, each name followed by its value
+fn signature(secret: &str, params: &[(&str, &str)]) -> anyhow::Result<String> {
+    let mut params = params.to_vec();
+    params.sort();
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    for (name, value) in params {
+        mac.update(name.as_bytes());
+        mac.update(value.as_bytes());
+    }
+    let digest = mac.finalize().into_bytes();
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Readings of a station given the response of the current conditions API, in imperial units
+pub fn parse_current(body: &str) -> anyhow::Result<Measurements> {
+    let raw: CurrentRaw =
+        serde_json::from_str(body).context("Current conditions parsing failed")?;
+
+    // readings of the ISS come first, other sensors such as AirLink report `temp` too
+    let mut records = raw
+        .sensors
+        .into_iter()
+        .flat_map(|sensor| sensor.data)
+        .collect::<Vec<_>>();
+    records.sort_by_key(|record| {
+        !(record.contains_key("wind_dir_last") || record.contains_key("wind_dir"))
+    });
+
+    let mut readings = HashMap::new();
+    for record in &records {
+        for (key, value) in record {
+            if let Some(value) = value.as_f64() {
+                readings.entry(key.as_str()).or_insert(value);
+            }
+        }
+    }
+    // the first of the keys that is reported, these differ between consoles
+    let number = |keys: &[&str]| keys.iter().find_map(|key| readings.get(key).copied());
+
+    let ts = records
+        .iter()
+        .filter_map(|record| record.get("ts")?.as_i64())
+        .max()
+        .ok_or_else(|| anyhow!("No readings"))?;
+    let update_time: DateTime<Utc> =
+        DateTime::from_timestamp(ts, 0).ok_or_else(|| anyhow!("Invalid timestamp: {}", ts))?;
+
+    let temperature = |keys: &[&str]| number(keys).and_then(|t| units::temperature(t, "F"));
+    let speed = |keys: &[&str]| {
+        number(keys)
+            .and_then(|s| units::speed(s, "mph"))
+            .map(|s| s.round() as u64)
+    };
+    let pressure = |keys: &[&str]| {
+        number(keys)
+            .and_then(|p| units::pressure(p, "inHg"))
+            .map(|p| p.round() as u64)
+    };
+    let precipitation = number(&["rainfall_daily_mm", "rain_day_mm"]).or_else(|| {
+        number(&["rainfall_daily_in", "rain_day_in"]).and_then(|p| units::precipitation(p, "in"))
+    });
+
+    Ok(Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        humidity: number(&["hum", "hum_out"]).map(|v| v.round() as u64),
+        precipitation,
+        pressure: pressure(&["bar_absolute"]),
+        temperature: temperature(&["temp", "temp_out"]),
+        wind_direction: number(&[
+            "wind_dir_scalar_avg_last_10_min",
+            "wind_dir_last",
+            "wind_dir",
+        ])
+        .map(|d| wind_direction_name(d).to_owned()),
+        wind_speed: speed(&[
+            "wind_speed_avg_last_10_min",
+            "wind_speed_10_min_avg",
+            "wind_speed_last",
+            "wind_speed",
+        ]),
+        gusts_speed: speed(&["wind_speed_hi_last_10_min", "wind_gust_10_min"]),
+        dew_point: temperature(&["dew_point", "dew_point_out"]),
+        pressure_sea_level: pressure(&["bar_sea_level", "bar"]),
+        solar_radiation: number(&["solar_rad"]).map(|v| v.round() as u64),
+        uv_index: number(&["uv_index", "uv"]),
+        ..Default::default()
+    })
+}
+
+impl Downloader for WeatherlinkV2Downloader {
+    fn name(&self) -> &'static str {
+        "weatherlink_v2"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_owned()
+    }
+
+    fn station_url(&self, id: &str) -> String {
+        format!("{}current/{}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "pressure",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "dew_point",
+            "pressure_sea_level",
+            "solar_radiation",
+            "uv_index",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        5
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let id = station_id(url).ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+        let api_key = spin_sdk::variables::get("weatherlink_api_key").unwrap_or_default();
+        let api_secret = spin_sdk::variables::get("weatherlink_api_secret").unwrap_or_default();
+        if api_key.is_empty() || api_secret.is_empty() {
+            anyhow::bail!("WeatherLink API key or secret not set");
+        }
+
+        // requests are signed with the secret, which itself is never sent
+        let t = Utc::now().timestamp().to_string();
+        let api_signature = signature(
+            &api_secret,
+            &[("api-key", &api_key), ("station-id", id), ("t", &t)],
+        )?;
+
+        let mut api_url = url::Url::parse(&self.station_url(id))?;
+        api_url
+            .query_pairs_mut()
+            .append_pair("api-key", &api_key)
+            .append_pair("t", &t)
+            .append_pair("api-signature", &api_signature);
+
+        let request = Request::builder()
+            .method(Method::Get)
+            .uri(api_url.as_str())
+            .build();
+
+        let response: Response = spin_sdk::http::send(request).await?;
+        match *response.status() {
+            200 => parse_current(&String::from_utf8_lossy(response.body())),
+            // stations of other accounts are not accessible with the key
+            403 => anyhow::bail!("Station not accessible with the API key: {}", id),
+            status => anyhow::bail!("Unexpected response status: {}", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a WeatherLink Live with the ISS, its barometer and inside sensor, and an AirLink
+    // listed first, which reports `temp`, `hum` and `dew_point` too
+    const CURRENT: &str = include_str!("fixtures/weatherlink_v2_current.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn readings_of_the_iss() {
+        let m = parse_current(CURRENT).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 10:31"));
+        // those of the ISS, not of the AirLink
+        assert_close(m.temperature, 20.11);
+        assert_eq!(m.humidity, Some(61));
+        assert_close(m.dew_point, 12.5);
+        // averages over 10 minutes take precedence over the last readings
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        assert_eq!(m.wind_speed, Some(12));
+        assert_eq!(m.gusts_speed, Some(24));
+        // reported in mm already
+        assert_close(m.precipitation, 3.0);
+        assert_eq!(m.pressure, Some(982));
+        assert_eq!(m.pressure_sea_level, Some(1016));
+        assert_eq!(m.solar_radiation, Some(642));
+        assert_close(m.uv_index, 5.3);
+    }
+
+    #[test]
+    fn no_readings() {
+        assert!(parse_current(r#"{"sensors": []}"#).is_err());
+        assert!(parse_current(r#"{"sensors": [{"data": [{"temp": 68.2}]}]}"#).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::collectors::common::wind_direction_name;
+use crate::collectors::Downloader;
+use crate::measurements::{Fields, Measurements};
+use anyhow::{anyhow, Context};
+use chrono::DateTime;
+use serde::Deserialize;
+use spin_sdk::http::{Method, Request, Response};
+
+pub const BASE_URL: &str = "https://www.windguru.cz/";
+const API_URL: &str = "https://www.windguru.cz/int/iapi.php";
+
+const KMH_PER_KNOT: f64 = 1.852;
+
+pub struct WindguruDownloader {}
+
+// wind is reported in knots
+#[derive(Deserialize, Debug)]
+struct CurrentRaw {
+    unixtime: i64,
+    wind_avg: Option<f64>,
+    wind_max: Option<f64>,
+    wind_direction: Option<f64>,
+    temperature: Option<f64>,
+    rh: Option<f64>,
+    mslp: Option<f64>,
+}
+
+/// Station ID given the URL of the station page, e.g. `1234`
+fn station_id(url: &str) -> Option<&str> {
+    let (_, id) = url.split_once("/station/")?;
+    let id = id.split(['/', '?', '#']).next()?;
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_digit())).then_some(id)
+}
+
+/// Readings of a station given the response of the `station_data_current` query
+pub fn parse_current(body: &str) -> anyhow::Result<Measurements> {
+    let raw: CurrentRaw = serde_json::from_str(body).context("Station data parsing failed")?;
+    let update_time =
+        DateTime::from_timestamp(raw.unixtime, 0).ok_or_else(|| anyhow!("Invalid timestamp"))?;
+    let kmh = |knots: f64| (knots * KMH_PER_KNOT).round() as u64;
+
+    Ok(Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        humidity: raw.rh.map(|v| v.round() as u64),
+        temperature: raw.temperature,
+        wind_direction: raw
+            .wind_direction
+            .map(|d| wind_direction_name(d).to_owned()),
+        wind_speed: raw.wind_avg.map(kmh),
+        gusts_speed: raw.wind_max.map(kmh),
+        pressure_sea_level: raw.mslp.map(|v| v.round() as u64),
+        ..Default::default()
+    })
+}
+
+impl Downloader for WindguruDownloader {
+    fn name(&self) -> &'static str {
+        "windguru"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_owned()
+    }
+
+    fn station_url(&self, id: &str) -> String {
+        format!("{}station/{}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "humidity",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "pressure_sea_level",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        5
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let id = station_id(url).ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+        let api_url = format!("{}?q=station_data_current&id_station={}", API_URL, id);
+
+        // the API answers only requests coming from the station page, it is undocumented
+        // and may stop working at any time, see the README
+        let request = Request::builder()
+            .method(Method::Get)
+            .header("Referer", self.station_url(id))
+            .uri(api_url)
+            .build();
+
+        let response: Response = spin_sdk::http::send(request).await?;
+        if *response.status() != 200 {
+            anyhow::bail!("Unexpected response status: {}", response.status());
+        }
+        parse_current(&String::from_utf8_lossy(response.body()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as answered to the station page, wind in knots
+    const CURRENT: &str = include_str!("fixtures/windguru_current.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn current_readings() {
+        let m = parse_current(CURRENT).unwrap();
+        // from the timestamp, in UTC, not from the local time
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 10:31"));
+        // 8.7 and 13.4 knots
+        assert_eq!(m.wind_speed, Some(16));
+        assert_eq!(m.gusts_speed, Some(25));
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        assert_close(m.temperature, 21.6);
+        assert_eq!(m.humidity, Some(63));
+        assert_eq!(m.pressure_sea_level, Some(1013));
+        assert_eq!(m.pressure, None);
+    }
+
+    #[test]
+    fn missing_sensors() {
+        let m = parse_current(r#"{"unixtime": 1718361065, "wind_avg": 8.7}"#).unwrap();
+        assert_eq!(m.wind_speed, Some(16));
+        assert_eq!(m.gusts_speed, None);
+        assert_eq!(m.temperature, None);
+    }
+
+    #[test]
+    fn invalid_responses() {
+        // an error instead of readings, e.g. for an unknown station
+        assert!(parse_current(r#"{"return": "error", "message": "Station not found"}"#).is_err());
+        assert!(parse_current(r#"{"unixtime": 9223372036854775807}"#).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::collectors::common::wind_direction_name;
+use crate::collectors::units;
+use crate::collectors::Downloader;
+use crate::measurements::{Fields, Measurements};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use spin_sdk::http::{Method, Request, Response};
+use std::collections::HashMap;
+
+pub const BASE_URL: &str = "https://www.wunderground.com/";
+const API_URL: &str = "https://api.weather.com/v2/pws/observations/current";
+
+pub struct WundergroundDownloader {}
+
+// readings of the unit system picked by the `units` parameter are under its own key
+const UNIT_SYSTEMS: &[UnitSystem] = &[
+    UnitSystem {
+        key: "metric",
+        temperature: "C",
+        speed: "km/h",
+        pressure: "hPa",
+        precipitation: "mm",
+    },
+    UnitSystem {
+        key: "metric_si",
+        temperature: "C",
+        speed: "m/s",
+        pressure: "hPa",
+        precipitation: "mm",
+    },
+    UnitSystem {
+        key: "uk_hybrid",
+        temperature: "C",
+        speed: "mph",
+        pressure: "hPa",
+        precipitation: "mm",
+    },
+    UnitSystem {
+        key: "imperial",
+        temperature: "F",
+        speed: "mph",
+        pressure: "inHg",
+        precipitation: "in",
+    },
+];
+
+struct UnitSystem {
+    key: &'static str,
+    temperature: &'static str,
+    speed: &'static str,
+    pressure: &'static str,
+    precipitation: &'static str,
+}
+
+#[derive(Deserialize, Debug)]
+struct ObservationsRaw {
+    observations: Vec<ObservationRaw>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct ObservationRaw {
+    obsTimeUtc: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    humidity: Option<f64>,
+    winddir: Option<f64>,
+    #[serde(flatten)]
+    systems: HashMap<String, serde_json::Value>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct ReadingsRaw {
+    temp: Option<f64>,
+    dewpt: Option<f64>,
+    windSpeed: Option<f64>,
+    windGust: Option<f64>,
+    pressure: Option<f64>,
+    precipTotal: Option<f64>,
+}
+
+/// Station ID given the URL of the dashboard, e.g. `IBERGA12`
+fn station_id(url: &str) -> Option<&str> {
+    let (_, id) = url.split_once("/pws/")?;
+    let id = id.split(['/', '?', '#']).next()?;
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())).then_some(id)
+}
+
+/// Readings of a station given the response of the current observations API
+pub fn parse_observations(body: &str) -> anyhow::Result<Measurements> {
+    let raw: ObservationsRaw = serde_json::from_str(body).context("Observations parsing failed")?;
+    let mut observation = raw
+        .observations
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No observations"))?;
+
+    let (system, readings) = UNIT_SYSTEMS
+        .iter()
+        .find_map(|system| Some((system, observation.systems.remove(system.key)?)))
+        .ok_or_else(|| anyhow!("Unknown unit system"))?;
+    let readings: ReadingsRaw = serde_json::from_value(readings)?;
+
+    let update_time: DateTime<Utc> = observation.obsTimeUtc.parse()?;
+    let temperature = |t: f64| units::temperature(t, system.temperature);
+    let speed = |s: f64| units::speed(s, system.speed).map(|s| s.round() as u64);
+
+    Ok(Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        humidity: observation.humidity.map(|v| v.round() as u64),
+        precipitation: readings
+            .precipTotal
+            .and_then(|p| units::precipitation(p, system.precipitation)),
+        temperature: readings.temp.and_then(temperature),
+        wind_direction: observation
+            .winddir
+            .map(|d| wind_direction_name(d).to_owned()),
+        wind_speed: readings.windSpeed.and_then(speed),
+        gusts_speed: readings.windGust.and_then(speed),
+        dew_point: readings.dewpt.and_then(temperature),
+        // stations report the pressure reduced to sea level
+        pressure_sea_level: readings
+            .pressure
+            .and_then(|p| units::pressure(p, system.pressure))
+            .map(|p| p.round() as u64),
+        latitude: observation.lat,
+        longitude: observation.lon,
+        ..Default::default()
+    })
+}
+
+impl Downloader for WundergroundDownloader {
+    fn name(&self) -> &'static str {
+        "wunderground"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_owned()
+    }
+
+    fn station_url(&self, id: &str) -> String {
+        format!("{}dashboard/pws/{}", BASE_URL, id)
+    }
+
+    fn provided_fields(&self) -> &'static [&'static str] {
+        &[
+            "update_time",
+            "humidity",
+            "precipitation",
+            "temperature",
+            "wind_direction",
+            "wind_speed",
+            "gusts_speed",
+            "dew_point",
+            "pressure_sea_level",
+            "latitude",
+            "longitude",
+        ]
+    }
+
+    fn update_interval_minutes(&self) -> i64 {
+        5
+    }
+
+    async fn try_download(&self, url: &str, _fields: &Fields) -> anyhow::Result<Measurements> {
+        let id = station_id(url).ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+        let api_key = spin_sdk::variables::get("wunderground_api_key").unwrap_or_default();
+        if api_key.is_empty() {
+            anyhow::bail!("Weather Underground API key not set");
+        }
+
+        let mut api_url = url::Url::parse(API_URL)?;
+        api_url
+            .query_pairs_mut()
+            .append_pair("stationId", id)
+            .append_pair("format", "json")
+            .append_pair("units", "m")
+            .append_pair("numericPrecision", "decimal")
+            .append_pair("apiKey", &api_key);
+
+        let request = Request::builder()
+            .method(Method::Get)
+            .uri(api_url.as_str())
+            .build();
+
+        let response: Response = spin_sdk::http::send(request).await?;
+        match *response.status() {
+            200 => parse_observations(&String::from_utf8_lossy(response.body())),
+            // offline stations have no current observations
+            204 => anyhow::bail!("No recent observations: {}", id),
+            status => anyhow::bail!("Unexpected response status: {}", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the same kind of station, answered in the unit system it was asked for
+    const IMPERIAL: &str = include_str!("fixtures/wunderground_imperial.json");
+    const METRIC: &str = include_str!("fixtures/wunderground_metric.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn imperial_units() {
+        let m = parse_observations(IMPERIAL).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 10:31"));
+        // 70.3 and 55.2 °F
+        assert_close(m.temperature, 21.28);
+        assert_close(m.dew_point, 12.89);
+        // 9.2 and 13.9 mph
+        assert_eq!(m.wind_speed, Some(15));
+        assert_eq!(m.gusts_speed, Some(22));
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        // 29.92 inHg and 0.13 in
+        assert_eq!(m.pressure_sea_level, Some(1013));
+        assert_close(m.precipitation, 3.3);
+        assert_eq!(m.humidity, Some(58));
+        assert_close(m.latitude, 42.12);
+        assert_close(m.longitude, -70.82);
+    }
+
+    #[test]
+    fn metric_units() {
+        let m = parse_observations(METRIC).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2024-06-14 10:31"));
+        assert_close(m.temperature, 24.1);
+        assert_close(m.dew_point, 12.2);
+        assert_eq!(m.wind_speed, Some(12));
+        assert_eq!(m.gusts_speed, Some(19));
+        assert_eq!(m.wind_direction.as_deref(), Some("SSW"));
+        assert_eq!(m.pressure_sea_level, Some(1016));
+        assert_close(m.precipitation, 0.3);
+        assert_eq!(m.humidity, Some(47));
+    }
+
+    #[test]
+    fn unknown_unit_system() {
+        let body = METRIC.replace("\"metric\"", "\"hybrid\"");
+        assert!(parse_observations(&body).is_err());
+        assert!(parse_observations(r#"{"observations": []}"#).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::collectors::common::wind_direction_degrees;
+use crate::measurements::dew_point;
+use chrono::NaiveDateTime;
+use serde_json::{json, Value};
+use spin_sdk::http::Request;
+
+const KEY_STATION: &str = "station";
+const KEY_LATITUDE: &str = "latitude";
+const KEY_LONGITUDE: &str = "longitude";
+
+const KMH_PER_KNOT: f64 = 1.852;
+
+/// Station as requested by the client, the label is what the user named it in the config.
+#[derive(Clone, Debug)]
+pub struct Station {
+    pub name: String,
+    pub label: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    GeoJson,
+    Metar,
+}
+
+impl Format {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "geojson" => Some(Format::GeoJson),
+            "metar" => Some(Format::Metar),
+            _ => None,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" => Some(Format::Json),
+            "text/csv" => Some(Format::Csv),
+            "application/geo+json" => Some(Format::GeoJson),
+            _ => None,
+        }
+    }
+
+    /// Picks the format from the `format` query parameter, falling back to the `Accept` header.
+    ///
+    /// JSON is the default, also when none of the accepted media types is supported.
+    pub fn negotiate(req: &Request, format: Option<&str>) -> Result<Self, String> {
+        if let Some(name) = format {
+            return Format::parse(name).ok_or_else(|| format!("Unsupported format: {}", name));
+        }
+
+        let accept = req
+            .header("accept")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let format = accept
+            .split(',')
+            .filter_map(|item| item.split(';').next())
+            .find_map(|media_type| Format::from_media_type(media_type.trim()));
+        Ok(format.unwrap_or(Format::Json))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::GeoJson => "application/geo+json",
+            Format::Metar => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// Measurement keys the format relies on, on top of the requested ones
+    pub fn required_fields(&self) -> &'static [&'static str] {
+        match self {
+            Format::GeoJson => &[KEY_LATITUDE, KEY_LONGITUDE],
+            Format::Metar => &[
+                "update_time",
+                "wind_direction",
+                "wind_speed",
+                "gusts_speed",
+                "temperature",
+                "humidity",
+                "dew_point",
+                "pressure",
+                "pressure_sea_level",
+            ],
+            _ => &[],
+        }
+    }
+
+    pub fn render(&self, stations: &[Station], measurements: Vec<Value>, units: Value) -> String {
+        match self {
+            Format::Json => json!({
+                "measurements": measurements,
+                "units": units,
+            })
+            .to_string(),
+            Format::Csv => render_csv(stations, &measurements, &units),
+            Format::GeoJson => render_geojson(stations, measurements, units).to_string(),
+            Format::Metar => render_metar(stations, &measurements),
+        }
+    }
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn csv_value(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::Null) | None => String::new(),
+        Some(Value::String(s)) => csv_escape(s),
+        Some(v) => csv_escape(&v.to_string()),
+    }
+}
+
+fn render_csv(stations: &[Station], measurements: &[Value], units: &Value) -> String {
+    let keys = units
+        .as_object()
+        .map(|u| u.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let header = std::iter::once(KEY_STATION.to_owned())
+        .chain(keys.iter().map(|key| {
+            match units.get(key).and_then(|u| u.as_str()).unwrap_or_default() {
+                "" => csv_escape(key),
+                unit => csv_escape(&format!("{} [{}]", key, unit)),
+            }
+        }))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let rows = stations.iter().zip(measurements).map(|(station, m)| {
+        std::iter::once(csv_escape(&station.name))
+            .chain(keys.iter().map(|key| csv_value(m.get(key))))
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+
+    std::iter::once(header)
+        .chain(rows)
+        .map(|line| line + "\r\n")
+        .collect()
+}
+
+fn render_geojson(stations: &[Station], measurements: Vec<Value>, units: Value) -> Value {
+    let features = stations
+        .iter()
+        .zip(measurements)
+        .map(|(station, m)| {
+            let mut properties = match m {
+                Value::Object(map) => map,
+                _ => serde_json::Map::new(),
+            };
+            let latitude = properties.remove(KEY_LATITUDE).and_then(|v| v.as_f64());
+            let longitude = properties.remove(KEY_LONGITUDE).and_then(|v| v.as_f64());
+            properties.insert(KEY_STATION.to_owned(), station.name.clone().into());
+
+            // stations with unknown location are kept as unlocated features
+            let geometry = match (latitude, longitude) {
+                (Some(lat), Some(lon)) => json!({"type": "Point", "coordinates": [lon, lat]}),
+                _ => Value::Null,
+            };
+            json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": properties,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+        "units": units,
+    })
+}
+
+fn metar_time(m: &Value) -> String {
+    m.get("update_time")
+        .and_then(|t| t.as_str())
+        .and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M").ok())
+        .map(|t| t.format("%d%H%MZ").to_string())
+        .unwrap_or_else(|| "//////Z".to_owned())
+}
+
+fn metar_wind(m: &Value) -> String {
+    let knots = |key: &str| {
+        m.get(key)
+            .and_then(|v| v.as_f64())
+            .map(|kmh| (kmh / KMH_PER_KNOT).round() as u64)
+    };
+    let Some(speed) = knots("wind_speed") else {
+        return "/////KT".to_owned();
+    };
+    if speed == 0 {
+        return "00000KT".to_owned();
+    }
+
+    let direction = m
+        .get("wind_direction")
+        .and_then(|v| v.as_str())
+        .and_then(wind_direction_degrees)
+        .map(|degrees| match (degrees / 10.0).round() as u64 * 10 {
+            0 => "360".to_owned(),
+            d => format!("{:03}", d),
+        })
+        .unwrap_or_else(|| "VRB".to_owned());
+    let gusts = knots("gusts_speed")
+        .filter(|&gusts| gusts > speed)
+        .map(|gusts| format!("G{:02}", gusts))
+        .unwrap_or_default();
+    format!("{}{:02}{}KT", direction, speed, gusts)
+}
+
+fn metar_temperature(celsius: Option<f64>) -> String {
+    match celsius.map(|t| t.round() as i64) {
+        Some(t) if t < 0 => format!("M{:02}", -t),
+        Some(t) => format!("{:02}", t),
+        None => "//".to_owned(),
+    }
+}
+
+/// Simplified METAR, e.g. `Ager 181230Z 22012G18KT 14/08 Q1013`.
+///
+/// The pressure reduced to sea level is used when available, otherwise the one reported
+/// by the provider, which is not always reduced.
+fn render_metar(stations: &[Station], measurements: &[Value]) -> String {
+    stations
+        .iter()
+        .zip(measurements)
+        .map(|(station, m)| {
+            let temperature = m.get("temperature").and_then(|v| v.as_f64());
+            let humidity = m.get("humidity").and_then(|v| v.as_f64());
+            // measured values are preferred over the derived ones
+            let dew_point = m
+                .get("dew_point")
+                .and_then(|v| v.as_f64())
+                .or_else(|| temperature.zip(humidity).map(|(t, h)| dew_point(t, h)));
+            let pressure = m
+                .get("pressure_sea_level")
+                .and_then(|v| v.as_f64())
+                .or_else(|| m.get("pressure").and_then(|v| v.as_f64()))
+                .map(|p| format!("Q{:04}", p.round() as u64))
+                .unwrap_or_else(|| "Q////".to_owned());
+
+            format!(
+                "{} {} {} {}/{} {}\n",
+                station.label.as_deref().unwrap_or(&station.name),
+                metar_time(m),
+                metar_wind(m),
+                metar_temperature(temperature),
+                metar_temperature(dew_point),
+                pressure,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,165 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use spin_sdk::key_value::Store;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+const STORE_NAME: &str = "stats";
+const KEY_PREFIX: &str = "health:";
+
+// consecutive failures after which a provider is reported as broken, as long as these are of
+// several stations, a single broken station URL must not flag the whole provider
+const BROKEN_FAILURE_STREAK: u64 = 3;
+const BROKEN_MIN_STATIONS: usize = 2;
+
+/// Health of a provider, stored under `health:<provider>` in the `stats` store.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct ProviderHealth {
+    successes: u64,
+    failures: u64,
+    failure_streak: u64,
+    last_success: Option<String>,
+    last_failure: Option<String>,
+    last_error: Option<String>,
+    // stations failing since the last success
+    #[serde(default)]
+    failing_stations: BTreeSet<String>,
+}
+
+impl ProviderHealth {
+    fn is_broken(&self) -> bool {
+        self.failure_streak >= BROKEN_FAILURE_STREAK
+            && self.failing_stations.len() >= BROKEN_MIN_STATIONS
+    }
+
+    fn apply(&mut self, outcome: Outcome) {
+        match outcome.error {
+            None => {
+                self.successes += 1;
+                self.failure_streak = 0;
+                self.failing_stations.clear();
+                self.last_success = Some(outcome.time);
+            }
+            Some(error) => {
+                self.failures += 1;
+                self.failure_streak += 1;
+                self.failing_stations.insert(outcome.station);
+                self.last_failure = Some(outcome.time);
+                self.last_error = Some(error);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Outcome {
+    provider: String,
+    station: String,
+    time: String,
+    error: Option<String>,
+}
+
+thread_local! {
+    // outcomes collected while handling the current request
+    static PENDING: RefCell<Vec<Outcome>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn record(provider: &str, station: &str, error: Option<&anyhow::Error>) {
+    let outcome = Outcome {
+        provider: provider.to_owned(),
+        station: station.to_owned(),
+        time: Utc::now().format("%Y-%m-%d %H:%M").to_string(),
+        error: error.map(|e| e.to_string()),
+    };
+    PENDING.with_borrow_mut(|pending| pending.push(outcome));
+}
+
+/// Applies outcomes collected while handling the current request to the stored health.
+pub fn flush() -> anyhow::Result<()> {
+    let pending = PENDING.take();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let store = Store::open(STORE_NAME)?;
+    let mut providers = pending
+        .iter()
+        .map(|o| o.provider.clone())
+        .collect::<Vec<_>>();
+    providers.sort();
+    providers.dedup();
+
+    let mut healths = providers
+        .into_iter()
+        .map(|provider| {
+            let key = format!("{}{}", KEY_PREFIX, provider);
+            let health = store.get_json::<ProviderHealth>(&key)?.unwrap_or_default();
+            Ok((provider, health))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for outcome in pending {
+        if let Some((_, health)) = healths.iter_mut().find(|(p, _)| *p == outcome.provider) {
+            health.apply(outcome);
+        }
+    }
+    for (provider, health) in healths {
+        store.set_json(format!("{}{}", KEY_PREFIX, provider), &health)?;
+    }
+    Ok(())
+}
+
+fn load(store: &Store, provider: &str) -> anyhow::Result<ProviderHealth> {
+    let health = store
+        .get_json::<ProviderHealth>(format!("{}{}", KEY_PREFIX, provider))?
+        .unwrap_or_default();
+    Ok(health)
+}
+
+/// Health report of the given providers, keyed by provider name.
+///
+/// Errors may quote upstream URLs and responses, so these are included on request only.
+pub fn report(providers: &[&str], with_errors: bool) -> anyhow::Result<serde_json::Value> {
+    let store = Store::open(STORE_NAME)?;
+
+    let mut report = serde_json::Map::new();
+    for &provider in providers {
+        let health = load(&store, provider)?;
+        let status = if health.is_broken() {
+            "broken"
+        } else if health.successes + health.failures == 0 {
+            "unknown"
+        } else {
+            "ok"
+        };
+
+        let mut value = serde_json::to_value(&health)?;
+        value["status"] = status.into();
+        value["failing_stations"] = health.failing_stations.len().into();
+        if !with_errors {
+            value["last_error"] = serde_json::Value::Null;
+        }
+        report.insert(provider.to_owned(), value);
+    }
+    Ok(serde_json::Value::Object(report))
+}
+
+/// Health counters of a provider, as exposed to monitoring.
+#[derive(Debug)]
+pub struct Counters {
+    pub successes: u64,
+    pub failures: u64,
+    pub failure_streak: u64,
+    pub broken: bool,
+}
+
+pub fn counters(provider: &str) -> anyhow::Result<Counters> {
+    let store = Store::open(STORE_NAME)?;
+    let health = load(&store, provider)?;
+    Ok(Counters {
+        successes: health.successes,
+        failures: health.failures,
+        failure_streak: health.failure_streak,
+        broken: health.is_broken(),
+    })
+}
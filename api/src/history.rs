@@ -0,0 +1,215 @@
+use crate::measurements::Measurements;
+use chrono::{NaiveDate, Utc};
+use spin_sdk::key_value::Store;
+use std::collections::BTreeMap;
+
+const STORE_NAME: &str = "history";
+const KEY_PREFIX: &str = "history:";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_RETENTION_DAYS: i64 = 7;
+
+fn retention_days() -> i64 {
+    spin_sdk::variables::get("history_retention_days")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+// the date goes first, so that old keys can be found without knowing the stations
+fn key(date: NaiveDate, url: &str) -> String {
+    format!("{}{}:{}", KEY_PREFIX, date.format(DATE_FORMAT), url)
+}
+
+fn prune(store: &Store, today: NaiveDate) -> anyhow::Result<()> {
+    let oldest = today - chrono::Duration::days(retention_days());
+    for key in store.get_keys()? {
+        let Some(rest) = key.strip_prefix(KEY_PREFIX) else {
+            continue;
+        };
+        let date = rest.split(':').next().unwrap_or_default();
+        match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+            Ok(date) if date >= oldest => {}
+            _ => store.delete(&key)?,
+        }
+    }
+    Ok(())
+}
+
+/// Appends the measurements to the readings of the station of the current day.
+///
+/// Readings with the same update time as the previous one are not stored again.
+pub fn append(url: &str, measurements: &Measurements) -> anyhow::Result<()> {
+    let store = Store::open(STORE_NAME)?;
+    let today = Utc::now().date_naive();
+    let key = key(today, url);
+
+    let mut readings = match store.get_json::<Vec<Measurements>>(&key)? {
+        Some(readings) => readings,
+        None => {
+            prune(&store, today)?;
+            Vec::new()
+        }
+    };
+    if readings.last().map(|m| &m.update_time) == Some(&measurements.update_time) {
+        return Ok(());
+    }
+    readings.push(measurements.clone());
+    store.set_json(&key, &readings)?;
+    Ok(())
+}
+
+/// Series split by the day of the update time, leaving out days outside `oldest..=today`
+fn by_day(
+    series: &[Measurements],
+    oldest: NaiveDate,
+    today: NaiveDate,
+) -> BTreeMap<NaiveDate, Vec<&Measurements>> {
+    let mut days: BTreeMap<NaiveDate, Vec<&Measurements>> = BTreeMap::new();
+    for measurements in series {
+        let date = measurements
+            .update_time
+            .as_deref()
+            .and_then(|t| t.get(..10))
+            .and_then(|t| NaiveDate::parse_from_str(t, DATE_FORMAT).ok());
+        match date {
+            Some(date) if date >= oldest && date <= today => {
+                days.entry(date).or_default().push(measurements)
+            }
+            _ => {}
+        }
+    }
+    days
+}
+
+/// Adds the readings of `series` missing in `readings`, returns whether there were any.
+fn merge(readings: &mut Vec<Measurements>, series: &[&Measurements]) -> bool {
+    let stored = readings.len();
+    for measurements in series {
+        if !readings
+            .iter()
+            .any(|m| m.update_time == measurements.update_time)
+        {
+            readings.push((*measurements).clone());
+        }
+    }
+    if readings.len() == stored {
+        return false;
+    }
+    readings.sort_by(|a, b| a.update_time.cmp(&b.update_time));
+    true
+}
+
+/// Merges past readings of the station, e.g. when the provider serves a whole series.
+///
+/// Readings are stored under the day of their update time, those stored already are skipped.
+pub fn extend(url: &str, series: &[Measurements]) -> anyhow::Result<()> {
+    let store = Store::open(STORE_NAME)?;
+    let today = Utc::now().date_naive();
+    let oldest = today - chrono::Duration::days(retention_days());
+
+    for (date, series) in by_day(series, oldest, today) {
+        let key = key(date, url);
+        let mut readings = store
+            .get_json::<Vec<Measurements>>(&key)?
+            .unwrap_or_default();
+        if merge(&mut readings, &series) {
+            store.set_json(&key, &readings)?;
+        }
+    }
+    Ok(())
+}
+
+/// Readings of the station over the last `days` days, oldest first.
+pub fn read(url: &str, days: i64) -> anyhow::Result<Vec<Measurements>> {
+    let store = Store::open(STORE_NAME)?;
+    let today = Utc::now().date_naive();
+
+    let mut readings = Vec::new();
+    for offset in (0..days.clamp(1, retention_days().max(1))).rev() {
+        let date = today - chrono::Duration::days(offset);
+        if let Some(day) = store.get_json::<Vec<Measurements>>(key(date, url))? {
+            readings.extend(day);
+        }
+    }
+    Ok(readings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(time: &str, temperature: Option<f64>) -> Measurements {
+        Measurements {
+            update_time: Some(time.to_owned()),
+            temperature,
+            ..Default::default()
+        }
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, DATE_FORMAT).unwrap()
+    }
+
+    fn times(readings: &[Measurements]) -> Vec<&str> {
+        readings
+            .iter()
+            .map(|m| m.update_time.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn series_by_day() {
+        let series = [
+            reading("2024-06-06 23:30", Some(14.2)),
+            reading("2024-06-13 23:30", Some(18.1)),
+            reading("2024-06-14 00:00", Some(17.8)),
+            reading("2024-06-14 00:30", None),
+            Measurements::default(),
+        ];
+        let days = by_day(&series, date("2024-06-07"), date("2024-06-14"));
+        // the day out of retention and the reading without a time are left out
+        assert_eq!(
+            days.keys().copied().collect::<Vec<_>>(),
+            [date("2024-06-13"), date("2024-06-14")]
+        );
+        assert_eq!(days[&date("2024-06-13")].len(), 1);
+        assert_eq!(days[&date("2024-06-14")].len(), 2);
+    }
+
+    #[test]
+    fn overlapping_series() {
+        // stored by the previous download of a series of 3 hours
+        let mut readings = vec![
+            reading("2024-06-14 09:30", Some(23.6)),
+            reading("2024-06-14 10:00", Some(24.3)),
+            // the latest period was missing the temperature back then
+            reading("2024-06-14 10:30", None),
+        ];
+        // the next download overlaps, and goes further back than the stored readings
+        let series = [
+            reading("2024-06-14 09:00", Some(23.1)),
+            reading("2024-06-14 10:00", Some(24.3)),
+            reading("2024-06-14 10:30", Some(24.9)),
+            reading("2024-06-14 11:00", Some(25.2)),
+        ];
+        let series = series.iter().collect::<Vec<_>>();
+
+        assert!(merge(&mut readings, &series));
+        assert_eq!(
+            times(&readings),
+            [
+                "2024-06-14 09:00",
+                "2024-06-14 09:30",
+                "2024-06-14 10:00",
+                "2024-06-14 10:30",
+                "2024-06-14 11:00"
+            ]
+        );
+        // readings stored already are kept as they are
+        assert_eq!(readings[3].temperature, None);
+        assert_eq!(readings[4].temperature, Some(25.2));
+
+        assert!(!merge(&mut readings, &series));
+        assert_eq!(readings.len(), 5);
+    }
+}
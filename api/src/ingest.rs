@@ -0,0 +1,187 @@
+use crate::collectors::common::wind_direction_name;
+use crate::collectors::units;
+use crate::measurements::Measurements;
+use chrono::{Duration, NaiveDateTime};
+use spin_sdk::key_value::Store;
+use std::collections::HashMap;
+
+const STORE_NAME: &str = "ingest";
+const KEY_PREFIX: &str = "station:";
+const MAX_STATION_ID_LENGTH: usize = 64;
+
+// stations report sensors that are not connected with this value
+const MISSING_VALUE: f64 = -9999.0;
+
+// clocks of the stations drift, readings from further ahead would shadow any later ones
+const MAX_CLOCK_AHEAD_MINUTES: i64 = 5;
+
+pub fn validate_station_id(id: &str) -> Result<(), String> {
+    let valid_chars = id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if id.is_empty() || id.len() > MAX_STATION_ID_LENGTH || !valid_chars {
+        return Err(format!("Invalid station ID: {}", id));
+    }
+    Ok(())
+}
+
+/// Readings given the parameters of a Wunderground or Ecowitt upload, both use imperial units.
+///
+/// `now` is the time of the upload in UTC, readings must not be timestamped much later.
+pub fn parse_upload(
+    params: &HashMap<String, String>,
+    now: NaiveDateTime,
+) -> anyhow::Result<Measurements> {
+    let number = |key: &str| {
+        params
+            .get(key)
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite() && *v > MISSING_VALUE)
+    };
+
+    // `now` is allowed by the Wunderground protocol
+    let update_time = match params.get("dateutc").map(|t| t.trim()) {
+        None | Some("now") => now,
+        Some(t) => NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S")
+            .map_err(|e| anyhow::anyhow!("Invalid dateutc: {}", e))?,
+    };
+    if update_time > now + Duration::minutes(MAX_CLOCK_AHEAD_MINUTES) {
+        anyhow::bail!("Invalid dateutc: {} is in the future", update_time);
+    }
+
+    let temperature = |key: &str| number(key).and_then(|t| units::temperature(t, "F"));
+    let speed = |key: &str| {
+        number(key)
+            .and_then(|s| units::speed(s, "mph"))
+            .map(|s| s.round() as u64)
+    };
+    let pressure = |key: &str| {
+        number(key)
+            .and_then(|p| units::pressure(p, "inHg"))
+            .map(|p| p.round() as u64)
+    };
+
+    Ok(Measurements {
+        update_time: Some(update_time.format("%Y-%m-%d %H:%M").to_string()),
+        humidity: number("humidity").map(|v| v.round() as u64),
+        precipitation: number("dailyrainin").and_then(|p| units::precipitation(p, "in")),
+        pressure: pressure("baromabsin"),
+        temperature: temperature("tempf"),
+        wind_direction: number("winddir").map(|d| wind_direction_name(d).to_owned()),
+        wind_speed: speed("windspeedmph"),
+        gusts_speed: speed("windgustmph"),
+        dew_point: temperature("dewptf"),
+        // Wunderground calls the pressure reduced to sea level `baromin`
+        pressure_sea_level: pressure("baromrelin").or_else(|| pressure("baromin")),
+        ..Default::default()
+    })
+}
+
+/// Keeps the latest readings of the station under `station:<id>` in the `ingest` store.
+pub fn save(id: &str, measurements: &Measurements) -> anyhow::Result<()> {
+    let store = Store::open(STORE_NAME)?;
+    store.set_json(format!("{}{}", KEY_PREFIX, id), measurements)?;
+    Ok(())
+}
+
+pub fn load(id: &str) -> anyhow::Result<Option<Measurements>> {
+    let store = Store::open(STORE_NAME)?;
+    store.get_json::<Measurements>(format!("{}{}", KEY_PREFIX, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as sent by a station using the Wunderground protocol
+    const WUNDERGROUND_QUERY: &str = "ID=KCASANFR5&PASSWORD=XXXXXX&dateutc=2025-06-14+10%3A32%3A15\
+        &winddir=230&windspeedmph=12&windgustmph=17.5&tempf=70&rainin=0&dailyrainin=0.13\
+        &baromin=29.92&dewptf=58.2&humidity=66&softwaretype=WeatherLink%20v1.0\
+        &action=updateraw";
+
+    // as posted by an Ecowitt gateway with a customized server
+    const ECOWITT_FORM: &str = "PASSKEY=0123456789ABCDEF0123456789ABCDEF\
+        &stationtype=EasyWeatherPro_V5.1.6&runtime=3&dateutc=2025-06-14+10:32:15\
+        &tempinf=72.5&humidityin=45&baromrelin=29.921&baromabsin=28.532&tempf=68.9\
+        &humidity=62&winddir=247&windspeedmph=9.17&windgustmph=13.87&maxdailygust=18.34\
+        &solarradiation=512.34&uv=5&rainratein=0.000&eventrainin=0.000&hourlyrainin=0.000\
+        &dailyrainin=0.130&weeklyrainin=0.260&monthlyrainin=1.020&totalrainin=12.350\
+        &wh65batt=0&freq=868M&model=WS2900_V2.02.03";
+
+    fn params(query: &str) -> HashMap<String, String> {
+        url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    fn at(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("missing reading");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn wunderground_upload() {
+        let m = parse_upload(&params(WUNDERGROUND_QUERY), at("2025-06-14 10:32:20")).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:32"));
+        assert_close(m.temperature, 21.11);
+        assert_close(m.dew_point, 14.56);
+        assert_eq!(m.humidity, Some(66));
+        assert_eq!(m.wind_direction.as_deref(), Some("SW"));
+        assert_eq!(m.wind_speed, Some(19));
+        assert_eq!(m.gusts_speed, Some(28));
+        assert_close(m.precipitation, 3.30);
+        // only the pressure reduced to sea level is reported
+        assert_eq!(m.pressure, None);
+        assert_eq!(m.pressure_sea_level, Some(1013));
+    }
+
+    #[test]
+    fn ecowitt_upload() {
+        let m = parse_upload(&params(ECOWITT_FORM), at("2025-06-14 10:32:20")).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:32"));
+        // outdoor readings, not those of the gateway indoors
+        assert_close(m.temperature, 20.5);
+        assert_eq!(m.humidity, Some(62));
+        assert_eq!(m.dew_point, None);
+        assert_eq!(m.wind_direction.as_deref(), Some("WSW"));
+        assert_eq!(m.wind_speed, Some(15));
+        assert_eq!(m.gusts_speed, Some(22));
+        assert_close(m.precipitation, 3.30);
+        assert_eq!(m.pressure, Some(966));
+        assert_eq!(m.pressure_sea_level, Some(1013));
+    }
+
+    #[test]
+    fn missing_sensors() {
+        let query = "dateutc=now&tempf=-9999&windspeedmph=-9999&humidity=&winddir=180";
+        let now = at("2025-06-14 10:32:20");
+        let m = parse_upload(&params(query), now).unwrap();
+        assert_eq!(m.update_time.as_deref(), Some("2025-06-14 10:32"));
+        assert_eq!(m.temperature, None);
+        assert_eq!(m.wind_speed, None);
+        assert_eq!(m.humidity, None);
+        assert_eq!(m.wind_direction.as_deref(), Some("S"));
+    }
+
+    #[test]
+    fn timestamps_ahead() {
+        let upload = |dateutc: &str| {
+            let query = format!("dateutc={}&tempf=70", dateutc);
+            parse_upload(&params(&query), at("2025-06-14 10:32:20"))
+        };
+        // clocks running slightly ahead are tolerated
+        assert!(upload("2025-06-14+10:35:00").is_ok());
+        assert!(upload("2025-06-14+10:45:00").is_err());
+        assert!(upload("2035-01-01+00:00:00").is_err());
+        assert!(upload("2025-06-14").is_err());
+    }
+}
@@ -1,47 +1,81 @@
+mod alerts;
+mod auth;
+mod cache;
 mod collectors;
+mod formats;
+mod health;
+mod history;
+mod ingest;
 mod measurements;
+mod metrics;
+mod ratelimit;
+mod stats;
 
 use crate::measurements::Measurements;
-use collectors::{Downloader, AemetDownloader, MeteocatDownloader, MeteoclimaticDownloader, WeatherlinkDownloader, OpenWindMapDownloader};
-use futures::stream::{self, StreamExt};
-use measurements::get_units;
+use auth::{Principal, Scope};
+use collectors::{
+    Downloader,
+    AemetDownloader,
+    MeteocatDownloader,
+    MeteoclimaticDownloader,
+    WeatherlinkDownloader,
+    WeatherlinkV2Downloader,
+    OpenWindMapDownloader,
+    MetNoDownloader,
+    HolfuyDownloader,
+    WindguruDownloader,
+    WundergroundDownloader,
+    AmbientDownloader,
+    LocalDownloader,
+};
+use cache::Lookup;
+use formats::{Format, Station};
+use measurements::{get_units, is_forecast_key, unit_for_key, Fields};
+use ratelimit::{too_many_requests_resp, Limit};
+use serde::Deserialize;
 use serde_json::json;
-use spin_sdk::http::{IntoResponse, Request, Response};
-use spin_sdk::{http_component, key_value::Store};
+use sha2::{Digest, Sha256};
+use spin_sdk::http::{IntoResponse, Params, Request, Response, Router};
+use spin_sdk::http_component;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 const MAX_NUMBER_OF_MEASUREMENTS: usize = 50;
 
 // important only when smaller than MAX_NUMBER_OF_MEASUREMENTS
-const MAX_CONCURRENT_DOWNLOADS: usize = 100;
+pub(crate) const MAX_CONCURRENT_DOWNLOADS: usize = 100;
 
-fn log_req_info(req: &Request) -> anyhow::Result<()> {
+// readings are updated every few minutes at best
+const CACHE_MAX_AGE_SECS: u64 = 60;
+
+// how long to wait for a concurrent request fetching a station with no readings at hand
+const COALESCE_WAIT: Duration = Duration::from_secs(5);
+const COALESCE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn get_client_addr(req: &Request) -> &str {
     let client_addr: &str = req
         .header("spin-client-addr")
         .map(|v| v.as_str().unwrap_or("?!"))
         .unwrap_or("?");
 
+    client_addr.split(":").next().unwrap_or(client_addr)
+}
+
+fn log_req_info(req: &Request) -> anyhow::Result<String> {
+    let client_id = auth::anonymize(get_client_addr(req))?;
+
     let full_url: &str = req
         .header("spin-full-url")
         .map(|v| v.as_str().unwrap_or("?!"))
         .unwrap_or("?");
 
-    let client_addr = client_addr.split(":").next().unwrap_or(client_addr);
-
-    let store = Store::open("stats")?;
-    let zero = "0".as_bytes();
-    let count_bytes = store.get(client_addr)?.unwrap_or(zero.to_vec());
-    let mut count = String::from_utf8_lossy(&count_bytes)
-        .parse::<u64>()
-        .unwrap_or(0);
-    count += 1;
-    store.set(client_addr, count.to_string().as_bytes())?;
+    stats::record_request(&client_id);
 
-    log::info!("{}#{} {} {}", client_addr, count, req.method(), full_url);
-    Ok(())
+    log::info!("{} {} {}", client_id, req.method(), full_url);
+    Ok(client_id)
 }
 
-fn plain_text_resp(status: u16, message: &str) -> Response {
+pub(crate) fn plain_text_resp(status: u16, message: &str) -> Response {
     Response::builder()
         .status(status)
         .header("content-type", "text/plain")
@@ -49,60 +83,610 @@ fn plain_text_resp(status: u16, message: &str) -> Response {
         .build()
 }
 
-fn check_token(req: &Request) -> anyhow::Result<Option<Response>> {
-    let query_string = req.query();
-    let query_vector = querystring::querify(query_string);
-    let query: HashMap<_, _> = query_vector.into_iter().collect();
+/// Station in the POST request, a bare URL or a station of the UI config.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum StationSpec {
+    Url(String),
+    Labeled { url: String, label: String },
+}
+
+impl StationSpec {
+    fn into_station(self) -> Station {
+        match self {
+            StationSpec::Url(url) => Station {
+                name: url,
+                label: None,
+            },
+            StationSpec::Labeled { url, label } => Station {
+                name: url,
+                label: Some(label),
+            },
+        }
+    }
+}
+
+/// Body of the POST request, either a bare list of station URLs or an object with fields.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum MeasurementsRequest {
+    Urls(Vec<String>),
+    Selection {
+        stations: Vec<StationSpec>,
+        #[serde(default)]
+        fields: Vec<String>,
+    },
+}
+
+/// Station of a batch, the readings are known already unless it needs to be downloaded.
+struct Slot<'a> {
+    url: &'a str,
+    // seconds the cached readings of the provider stay fresh
+    max_age: i64,
+    readings: Option<Measurements>,
+    stale: Option<Measurements>,
+    claimed: bool,
+    // fetched by a concurrent request, with no readings to serve in the meantime
+    waiting: bool,
+}
+
+fn prepare<'a>(downloader: &impl Downloader, url: &'a str, fields: &Fields) -> Slot<'a> {
+    let mut slot = Slot {
+        url,
+        max_age: downloader.update_interval_minutes() * 60,
+        readings: None,
+        stale: None,
+        claimed: false,
+        waiting: false,
+    };
 
-    let expected_token = spin_sdk::variables::get("api_token")?;
+    match cache::lookup(url, slot.max_age, fields) {
+        Ok(Lookup::Fresh(measurements)) => {
+            slot.readings = Some(measurements);
+            return slot;
+        }
+        Ok(Lookup::Stale(measurements)) => slot.stale = Some(measurements),
+        Ok(Lookup::Miss) => {}
+        Err(e) => log::error!("{} while reading cache of: {}", e, url),
+    };
 
-    if let Some(token) = query.get("token") {
-        if token != &expected_token {
-            log::error!("Invalid token: {}", token);
-            return Ok(Some(plain_text_resp(400, "Invalid token")));
+    // concurrent requests get stale readings while one of them refreshes the station,
+    // without any readings at hand they wait for it to finish
+    slot.claimed = cache::claim(url).unwrap_or_else(|e| {
+        log::error!("{} while claiming fetch of: {}", e, url);
+        true
+    });
+    if !slot.claimed {
+        if slot.stale.is_some() {
+            log::info!("Fetch in progress, serving stale readings: {}", url);
+            slot.readings = slot.stale.take();
+        } else {
+            slot.waiting = true;
         }
+        return slot;
+    }
+    slot
+}
+
+/// Slots of the stations of a provider, those it can't fill in any field of are left empty.
+fn prepare_all<'a>(
+    downloader: &impl Downloader,
+    urls: Vec<&'a str>,
+    fields: &Fields,
+) -> Vec<Slot<'a>> {
+    if !downloader.provides(fields) && !urls.is_empty() {
+        log::info!("No requested fields provided, skipping: {}", urls.join(", "));
+        return urls
+            .into_iter()
+            .map(|url| Slot {
+                url,
+                max_age: 0,
+                readings: Some(Measurements::default()),
+                stale: None,
+                claimed: false,
+                waiting: false,
+            })
+            .collect();
+    }
+    urls.into_iter()
+        .map(|url| prepare(downloader, url, fields))
+        .collect()
+}
+
+/// Waits for concurrent requests fetching the stations of the waiting slots.
+///
+/// Stations whose fetch fails or takes longer than `COALESCE_WAIT` are fetched here too.
+/// The wait blocks, so it is done once for the slots of all providers before any download.
+fn wait_for_fetches(slots: &mut [&mut Slot], fields: &Fields) {
+    let started = Instant::now();
+    while slots.iter().any(|slot| slot.waiting) && started.elapsed() < COALESCE_WAIT {
+        std::thread::sleep(COALESCE_POLL_INTERVAL);
+        for slot in slots.iter_mut().filter(|slot| slot.waiting) {
+            match cache::lookup(slot.url, slot.max_age, fields) {
+                Ok(Lookup::Fresh(measurements)) => {
+                    slot.readings = Some(measurements);
+                    slot.waiting = false;
+                }
+                // released without readings, the other fetch failed
+                Ok(_) => slot.waiting = cache::in_flight(slot.url).unwrap_or(false),
+                Err(e) => {
+                    log::error!("{} while reading cache of: {}", e, slot.url);
+                    slot.waiting = false;
+                }
+            }
+        }
+    }
+
+    for slot in slots.iter_mut().filter(|slot| slot.waiting) {
+        log::info!("Concurrent fetch not done, fetching too: {}", slot.url);
+        slot.waiting = false;
+    }
+}
+
+/// Readings of stations of the same provider, downloading only those missing in the cache.
+async fn fetch<'a>(
+    downloader: impl Downloader,
+    mut slots: Vec<Slot<'a>>,
+    fields: &Fields,
+) -> Vec<(&'a str, Measurements)> {
+    let missing = slots
+        .iter()
+        .filter(|slot| slot.readings.is_none())
+        .map(|slot| slot.url)
+        .collect::<Vec<_>>();
+    let mut downloaded = if missing.is_empty() {
+        Vec::new()
     } else {
-        log::error!("Missing token");
-        return Ok(Some(plain_text_resp(400, "Missing token")));
+        downloader.download_batch(&missing, fields).await
     }
+    .into_iter();
 
-    Ok(None)
+    for slot in slots.iter_mut().filter(|slot| slot.readings.is_none()) {
+        let readings = match downloaded.next() {
+            Some(Ok(measurements)) => {
+                save(slot.url, fields, &measurements, !downloader.is_forecast());
+                measurements
+            }
+            // failed downloads fall back to the last readings, if there are any
+            _ => slot.stale.take().unwrap_or_default(),
+        };
+        slot.readings = Some(readings);
+    }
+    // released once saved, so that requests waiting for these find the readings
+    for slot in slots.iter().filter(|slot| slot.claimed) {
+        if let Err(e) = cache::release(slot.url) {
+            log::error!("{} while releasing fetch of: {}", e, slot.url);
+        }
+    }
+
+    slots
+        .into_iter()
+        .map(|slot| {
+            let mut readings = slot.readings.unwrap_or_default();
+            downloader.resolve(&mut readings);
+            (slot.url, readings)
+        })
+        .collect()
 }
 
-async fn dispatch(url: &str) -> Measurements {
-    // scheme and domain are case insensitive
-    let url_lower = url.to_lowercase();
+fn save(url: &str, fields: &Fields, measurements: &Measurements, keep_history: bool) {
+    if let Err(e) = cache::put(url, fields, measurements) {
+        log::error!("{} while caching: {}", e, url);
+    }
+    // partial readings would leave gaps in the history
+    if !keep_history || !fields.is_all() {
+        return;
+    }
+    if let Err(e) = history::append(url, measurements) {
+        log::error!("{} while saving history of: {}", e, url);
+    }
+}
 
+fn provider_names() -> Vec<&'static str> {
+    vec![
+        AemetDownloader {}.name(),
+        MeteocatDownloader {}.name(),
+        MeteoclimaticDownloader {}.name(),
+        WeatherlinkDownloader {}.name(),
+        WeatherlinkV2Downloader {}.name(),
+        OpenWindMapDownloader {}.name(),
+        MetNoDownloader {}.name(),
+        HolfuyDownloader {}.name(),
+        WindguruDownloader {}.name(),
+        WundergroundDownloader {}.name(),
+        AmbientDownloader {}.name(),
+        LocalDownloader {}.name(),
+    ]
+}
+
+/// Groups the stations by provider, so that each provider can fetch its stations at once.
+async fn dispatch<'a>(urls: Vec<&'a str>, fields: &Fields) -> Vec<(&'a str, Measurements)> {
     let aemet = AemetDownloader {};
     let meteocat = MeteocatDownloader {};
     let meteoclimatic = MeteoclimaticDownloader {};
     let weatherlink = WeatherlinkDownloader {};
+    let weatherlink_v2 = WeatherlinkV2Downloader {};
     let openwindmap = OpenWindMapDownloader {};
+    let metno = MetNoDownloader {};
+    let holfuy = HolfuyDownloader {};
+    let windguru = WindguruDownloader {};
+    let wunderground = WundergroundDownloader {};
+    let ambient = AmbientDownloader {};
+    let local = LocalDownloader {};
+
+    let mut aemet_urls = Vec::new();
+    let mut meteocat_urls = Vec::new();
+    let mut meteoclimatic_urls = Vec::new();
+    let mut weatherlink_urls = Vec::new();
+    let mut weatherlink_v2_urls = Vec::new();
+    let mut openwindmap_urls = Vec::new();
+    let mut metno_urls = Vec::new();
+    let mut holfuy_urls = Vec::new();
+    let mut windguru_urls = Vec::new();
+    let mut wunderground_urls = Vec::new();
+    let mut ambient_urls = Vec::new();
+    let mut local_urls = Vec::new();
+    let mut unsupported = Vec::new();
+
+    for url in urls {
+        // scheme and domain are case insensitive
+        let url_lower = url.to_lowercase();
 
-    if url_lower.starts_with(&aemet.base_url()) {
-        aemet.download(url).await
-    } else if url_lower.starts_with(&meteocat.base_url()) {
-        meteocat.download(url).await
-    } else if url_lower.starts_with(&meteoclimatic.base_url()) {
-        meteoclimatic.download(url).await
-    } else if url_lower.starts_with(&weatherlink.base_url()) {
-        weatherlink.download(url).await
-    } else if url_lower.starts_with(&openwindmap.base_url()) {
-        openwindmap.download(url).await
+        if url_lower.starts_with(&aemet.base_url()) {
+            aemet_urls.push(url);
+        } else if url_lower.starts_with(&meteocat.base_url()) {
+            meteocat_urls.push(url);
+        } else if url_lower.starts_with(&meteoclimatic.base_url()) {
+            meteoclimatic_urls.push(url);
+        } else if url_lower.starts_with(&weatherlink.base_url()) {
+            weatherlink_urls.push(url);
+        } else if url_lower.starts_with(&weatherlink_v2.base_url()) {
+            weatherlink_v2_urls.push(url);
+        } else if url_lower.starts_with(&openwindmap.base_url()) {
+            openwindmap_urls.push(url);
+        } else if url_lower.starts_with(&metno.base_url()) {
+            metno_urls.push(url);
+        } else if url_lower.starts_with(&holfuy.base_url()) {
+            holfuy_urls.push(url);
+        } else if url_lower.starts_with(&windguru.base_url()) {
+            windguru_urls.push(url);
+        } else if url_lower.starts_with(&wunderground.base_url()) {
+            wunderground_urls.push(url);
+        } else if url_lower.starts_with(&ambient.base_url()) {
+            ambient_urls.push(url);
+        } else if url_lower.starts_with(&local.base_url()) {
+            local_urls.push(url);
+        } else {
+            log::warn!("Unsupported station URL: {}", url);
+            unsupported.push((url, Measurements::default()));
+        }
+    }
+
+    let mut aemet_slots = prepare_all(&aemet, aemet_urls, fields);
+    let mut meteocat_slots = prepare_all(&meteocat, meteocat_urls, fields);
+    let mut meteoclimatic_slots = prepare_all(&meteoclimatic, meteoclimatic_urls, fields);
+    let mut weatherlink_slots = prepare_all(&weatherlink, weatherlink_urls, fields);
+    let mut weatherlink_v2_slots = prepare_all(&weatherlink_v2, weatherlink_v2_urls, fields);
+    let mut openwindmap_slots = prepare_all(&openwindmap, openwindmap_urls, fields);
+    let mut metno_slots = prepare_all(&metno, metno_urls, fields);
+    let mut holfuy_slots = prepare_all(&holfuy, holfuy_urls, fields);
+    let mut windguru_slots = prepare_all(&windguru, windguru_urls, fields);
+    let mut wunderground_slots = prepare_all(&wunderground, wunderground_urls, fields);
+    let mut ambient_slots = prepare_all(&ambient, ambient_urls, fields);
+    let mut local_slots = prepare_all(&local, local_urls, fields);
+
+    // stations being fetched by concurrent requests, of any provider
+    let mut waiting = aemet_slots
+        .iter_mut()
+        .chain(meteocat_slots.iter_mut())
+        .chain(meteoclimatic_slots.iter_mut())
+        .chain(weatherlink_slots.iter_mut())
+        .chain(weatherlink_v2_slots.iter_mut())
+        .chain(openwindmap_slots.iter_mut())
+        .chain(metno_slots.iter_mut())
+        .chain(holfuy_slots.iter_mut())
+        .chain(windguru_slots.iter_mut())
+        .chain(wunderground_slots.iter_mut())
+        .chain(ambient_slots.iter_mut())
+        .chain(local_slots.iter_mut())
+        .filter(|slot| slot.waiting)
+        .collect::<Vec<_>>();
+    wait_for_fetches(&mut waiting, fields);
+
+    let (
+        aemet,
+        meteocat,
+        meteoclimatic,
+        weatherlink,
+        weatherlink_v2,
+        openwindmap,
+        metno,
+        holfuy,
+        windguru,
+        wunderground,
+        ambient,
+        local,
+    ) = futures::join!(
+        fetch(aemet, aemet_slots, fields),
+        fetch(meteocat, meteocat_slots, fields),
+        fetch(meteoclimatic, meteoclimatic_slots, fields),
+        fetch(weatherlink, weatherlink_slots, fields),
+        fetch(weatherlink_v2, weatherlink_v2_slots, fields),
+        fetch(openwindmap, openwindmap_slots, fields),
+        fetch(metno, metno_slots, fields),
+        fetch(holfuy, holfuy_slots, fields),
+        fetch(windguru, windguru_slots, fields),
+        fetch(wunderground, wunderground_slots, fields),
+        fetch(ambient, ambient_slots, fields),
+        fetch(local, local_slots, fields),
+    );
+    [
+        aemet,
+        meteocat,
+        meteoclimatic,
+        weatherlink,
+        weatherlink_v2,
+        openwindmap,
+        metno,
+        holfuy,
+        windguru,
+        wunderground,
+        ambient,
+        local,
+        unsupported,
+    ]
+    .concat()
+}
+
+/// Expands `<provider>:<id>` into the station URL, full URLs are passed through.
+fn resolve_station(station: &str) -> Option<String> {
+    if station.contains("://") {
+        return Some(station.to_owned());
+    }
+    let (provider, id) = station.split_once(':')?;
+    let url = match provider.to_lowercase().as_str() {
+        "aemet" => AemetDownloader {}.station_url(id),
+        "meteocat" => MeteocatDownloader {}.station_url(id),
+        "meteoclimatic" => MeteoclimaticDownloader {}.station_url(id),
+        "weatherlink" => WeatherlinkDownloader {}.station_url(id),
+        "weatherlink_v2" => WeatherlinkV2Downloader {}.station_url(id),
+        "openwindmap" => OpenWindMapDownloader {}.station_url(id),
+        "metno" => MetNoDownloader {}.station_url(id),
+        "holfuy" => HolfuyDownloader {}.station_url(id),
+        "windguru" => WindguruDownloader {}.station_url(id),
+        "wunderground" => WundergroundDownloader {}.station_url(id),
+        "ambient" => AmbientDownloader {}.station_url(id),
+        "local" => LocalDownloader {}.station_url(id),
+        _ => return None,
+    };
+    Some(url)
+}
+
+/// Adds the forecast for the coordinates of each station, unless it is a forecast already.
+async fn add_forecasts(fetched: &mut HashMap<&str, Measurements>, fields: &Fields) {
+    let metno = MetNoDownloader {};
+    let points = fetched
+        .iter()
+        .filter(|(_, m)| !m.extra.keys().any(|key| is_forecast_key(key)))
+        .filter_map(|(url, m)| {
+            let coordinates = format!("{:.4},{:.4}", m.latitude?, m.longitude?);
+            Some((*url, metno.station_url(&coordinates)))
+        })
+        .collect::<Vec<_>>();
+
+    // nearby stations may share the forecast
+    let mut urls = points.iter().map(|(_, url)| url.as_str()).collect::<Vec<_>>();
+    urls.sort_unstable();
+    urls.dedup();
+    let mut slots = prepare_all(&metno, urls, fields);
+    wait_for_fetches(&mut slots.iter_mut().collect::<Vec<_>>(), fields);
+    let forecasts = fetch(metno, slots, fields)
+        .await
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    for (url, forecast_url) in points.iter() {
+        let (Some(m), Some(forecast)) = (fetched.get_mut(url), forecasts.get(forecast_url.as_str()))
+        else {
+            continue;
+        };
+        m.extra.extend(forecast.extra.clone());
+    }
+}
+
+async fn collect_measurements(urls: &[String], fields: &Fields) -> Vec<serde_json::Value> {
+    // stations listed more than once are fetched only once
+    let mut unique = urls.iter().map(|url| url.as_str()).collect::<Vec<_>>();
+    unique.sort_unstable();
+    unique.dedup();
+    if unique.len() < urls.len() {
+        log::info!("Duplicate stations requested: {}", urls.len() - unique.len());
+    }
+
+    // forecasts are looked up by the coordinates of the stations
+    let observed = if fields.requests_forecast() {
+        fields.clone().with(&["latitude", "longitude"])
     } else {
-        log::warn!("Unsupported station URL: {}", url);
-        Measurements::default()
+        fields.clone()
+    };
+    let mut fetched = dispatch(unique, &observed)
+        .await
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    if fields.requests_forecast() {
+        add_forecasts(&mut fetched, fields).await;
+    }
+
+    let fetched = fetched
+        .into_iter()
+        .map(|(url, measurements)| {
+            let value = serde_json::to_value(measurements).unwrap_or_default();
+            (url, fields.select(value))
+        })
+        .collect::<HashMap<_, _>>();
+
+    urls.iter()
+        .map(|url| fetched.get(url.as_str()).cloned().unwrap_or_default())
+        .collect()
+}
+
+fn handle_get(_: Request, _: Params) -> anyhow::Result<Response> {
+    let app_name = env!("CARGO_PKG_NAME");
+    let app_version = env!("CARGO_PKG_VERSION");
+    Ok(plain_text_resp(
+        200,
+        &format!("Hello from {app_name} v{app_version}"),
+    ))
+}
+
+fn handle_session(_: Request, _: Params) -> anyhow::Result<Response> {
+    let session = auth::issue_session()?;
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .header("cache-control", "no-store")
+        .body(serde_json::to_string(&session)?)
+        .build())
+}
+
+fn handle_stats(req: Request, _: Params) -> anyhow::Result<Response> {
+    if let Err(resp) = auth::authorize(&req, Scope::Admin)? {
+        return Ok(resp);
+    };
+
+    let query: HashMap<_, _> = querystring::querify(req.query()).into_iter().collect();
+    let days = query
+        .get("days")
+        .and_then(|d| d.parse::<i64>().ok())
+        .unwrap_or(7);
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(stats::report(days)?.to_string())
+        .build())
+}
+
+fn handle_health(req: Request, _: Params) -> anyhow::Result<Response> {
+    let principal = match auth::authorize(&req, Scope::Read)? {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
+
+    // errors are for maintainers, not for the anonymous sessions of the UI
+    let with_errors = principal.has_scope(Scope::Admin);
+    let data = json!({
+        "providers": health::report(&provider_names(), with_errors)?,
+    });
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(data.to_string())
+        .build())
+}
+
+fn handle_list_alerts(req: Request, _: Params) -> anyhow::Result<Response> {
+    if let Err(resp) = auth::authorize(&req, Scope::Admin)? {
+        return Ok(resp);
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(alerts::list()?.to_string())
+        .build())
+}
+
+fn handle_put_alert(req: Request, params: Params) -> anyhow::Result<Response> {
+    if let Err(resp) = auth::authorize(&req, Scope::Admin)? {
+        return Ok(resp);
+    };
+
+    let name = params.get("name").unwrap_or_default();
+    if let Err(e) = alerts::validate_name(name) {
+        return Ok(plain_text_resp(400, &e));
+    }
+
+    let mut rule = match serde_json::from_slice::<alerts::Rule>(req.body()) {
+        Ok(rule) => rule,
+        Err(e) => return Ok(plain_text_resp(400, &format!("Invalid rule: {}", e))),
+    };
+    // rules are matched with fetched stations by URL
+    rule.station = match resolve_station(&rule.station) {
+        Some(url) => url,
+        None => {
+            return Ok(plain_text_resp(
+                400,
+                &format!("Invalid station: {}", rule.station),
+            ))
+        }
+    };
+    if let Err(e) = rule.validate() {
+        return Ok(plain_text_resp(400, &e));
     }
+
+    alerts::put(name, &rule)?;
+    log::info!("Alert rule saved: {}", name);
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&rule)?)
+        .build())
 }
 
-async fn handle_post(req: &Request) -> anyhow::Result<Response> {
-    if let Some(resp) = check_token(req)? {
+fn handle_delete_alert(req: Request, params: Params) -> anyhow::Result<Response> {
+    if let Err(resp) = auth::authorize(&req, Scope::Admin)? {
         return Ok(resp);
     };
 
+    let name = params.get("name").unwrap_or_default();
+    if !alerts::delete(name)? {
+        return Ok(plain_text_resp(404, "No such rule"));
+    }
+    log::info!("Alert rule deleted: {}", name);
+    Ok(Response::builder().status(204).build())
+}
+
+/// Applies the rate limit of the API key the request carries, otherwise that of the client.
+/// Clients with a key are held to the limit of the key only, sessions are limited per client.
+fn check_rate_limit(req: &Request, client_id: &str) -> anyhow::Result<Option<Response>> {
+    let (key, limit) = match auth::api_key(req)? {
+        Some(Principal::ApiKey {
+            name, rate_limit, ..
+        }) => (format!("key:{}", name), rate_limit),
+        _ => (format!("client:{}", client_id), Limit::per_client()),
+    };
+    if let Some(retry_after) = ratelimit::take(&key, limit)? {
+        log::error!("Rate limit exceeded by {}", key);
+        return Ok(Some(too_many_requests_resp(retry_after)));
+    }
+    Ok(None)
+}
+
+async fn handle_post(req: Request, _: Params) -> anyhow::Result<Response> {
+    let principal = match auth::authorize(&req, Scope::Read)? {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
+    log::info!("Authorized as {}", principal.name());
+
+    let query: HashMap<_, _> = querystring::querify(req.query()).into_iter().collect();
+    let format = match Format::negotiate(&req, query.get("format").copied()) {
+        Ok(format) => format,
+        Err(e) => return Ok(plain_text_resp(400, &e)),
+    };
+
     let body_bytes = req.body();
-    let urls = match serde_json::from_slice::<Vec<String>>(body_bytes) {
-        Ok(v) => v,
+    let (stations, fields) = match serde_json::from_slice::<MeasurementsRequest>(body_bytes) {
+        Ok(MeasurementsRequest::Urls(urls)) => {
+            let stations = urls.into_iter().map(|url| StationSpec::Url(url).into_station());
+            (stations.collect::<Vec<_>>(), Vec::new())
+        }
+        Ok(MeasurementsRequest::Selection { stations, fields }) => {
+            let stations = stations.into_iter().map(StationSpec::into_station);
+            (stations.collect(), fields)
+        }
         Err(e) => {
             log::error!("Invalid configuration data: {}", e);
             return Ok(plain_text_resp(
@@ -112,26 +696,256 @@ async fn handle_post(req: &Request) -> anyhow::Result<Response> {
         }
     };
 
-    if urls.len() > MAX_NUMBER_OF_MEASUREMENTS {
+    if stations.len() > MAX_NUMBER_OF_MEASUREMENTS {
         log::error!(
             "Too many measurements requested: {} (max: {})",
-            urls.len(),
+            stations.len(),
             MAX_NUMBER_OF_MEASUREMENTS
         );
         return Ok(plain_text_resp(400, "Too many measurements requested at once"));
     }
 
-    let measurements = stream::iter(urls)
-        .map(|url| async move { dispatch(url.as_str()).await })
-        .buffered(MAX_CONCURRENT_DOWNLOADS)
-        .collect::<Vec<_>>()
-        .await;
+    // configs are edited by hand, so unknown keys are skipped instead of rejected
+    let (known, unknown): (Vec<_>, Vec<_>) = fields
+        .into_iter()
+        .partition(|f| unit_for_key(f).is_some());
+    if !unknown.is_empty() {
+        log::warn!("Unknown fields requested: {}", unknown.join(", "));
+    }
+    let fields = Fields::new(known).with(format.required_fields());
+
+    // stations are given as URLs or as `<provider>:<id>`, the same as in the GET form
+    let mut urls = Vec::with_capacity(stations.len());
+    for station in stations.iter() {
+        match resolve_station(&station.name) {
+            Some(url) => urls.push(url),
+            None => {
+                log::error!("Invalid station: {}", station.name);
+                return Ok(plain_text_resp(400, &format!("Invalid station: {}", station.name)));
+            }
+        }
+    }
+    let measurements = collect_measurements(&urls, &fields).await;
+    let body = format.render(&stations, measurements, fields.units());
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", format.content_type())
+        .body(body)
+        .build())
+}
+
+// Anyone can obtain a read session anyway, so the GET form does not require one.
+// This keeps it usable for bookmarks and embedding. Rate limits still apply.
+async fn handle_get_measurements(req: Request, _: Params) -> anyhow::Result<Response> {
+    let query = url::form_urlencoded::parse(req.query().as_bytes())
+        .into_owned()
+        .collect::<Vec<_>>();
+
+    let stations = query
+        .iter()
+        .filter(|(k, _)| k == "station")
+        .map(|(_, v)| v.as_str())
+        .collect::<Vec<_>>();
+    // labels are matched with the stations by position
+    let labels = query
+        .iter()
+        .filter(|(k, _)| k == "label")
+        .map(|(_, v)| v.as_str())
+        .collect::<Vec<_>>();
+    let keys = query
+        .iter()
+        .filter(|(k, _)| k == "fields")
+        .flat_map(|(_, v)| v.split(','))
+        .map(|f| f.trim().to_owned())
+        .filter(|f| !f.is_empty())
+        .collect::<Vec<_>>();
+
+    let format = query
+        .iter()
+        .find(|(k, _)| k == "format")
+        .map(|(_, v)| v.as_str());
+    let format = match Format::negotiate(&req, format) {
+        Ok(format) => format,
+        Err(e) => return Ok(plain_text_resp(400, &e)),
+    };
+
+    if stations.is_empty() {
+        return Ok(plain_text_resp(400, "No stations requested"));
+    }
+    if stations.len() > MAX_NUMBER_OF_MEASUREMENTS {
+        log::error!(
+            "Too many measurements requested: {} (max: {})",
+            stations.len(),
+            MAX_NUMBER_OF_MEASUREMENTS
+        );
+        return Ok(plain_text_resp(400, "Too many measurements requested at once"));
+    }
+
+    let mut urls = Vec::with_capacity(stations.len());
+    for &station in stations.iter() {
+        match resolve_station(station) {
+            Some(url) => urls.push(url),
+            None => {
+                log::error!("Invalid station: {}", station);
+                return Ok(plain_text_resp(400, &format!("Invalid station: {}", station)));
+            }
+        }
+    }
+
+    if let Some(key) = keys.iter().find(|k| unit_for_key(k).is_none()) {
+        log::error!("Invalid field: {}", key);
+        return Ok(plain_text_resp(400, &format!("Invalid field: {}", key)));
+    }
+    let fields = Fields::new(keys).with(format.required_fields());
+
+    let measurements = collect_measurements(&urls, &fields).await;
+
+    let stations = stations
+        .iter()
+        .enumerate()
+        .map(|(i, s)| Station {
+            name: s.to_string(),
+            label: labels.get(i).map(|l| l.to_string()),
+        })
+        .collect::<Vec<_>>();
+    let body = format.render(&stations, measurements, fields.units());
+
+    // the body itself, as `update_time` may not be among the selected fields
+    let mut hasher = Sha256::new();
+    hasher.update(format.content_type().as_bytes());
+    hasher.update(body.as_bytes());
+    let etag = format!("\"{:x}\"", hasher.finalize());
+    let cache_control = format!("public, max-age={}", CACHE_MAX_AGE_SECS);
+
+    let if_none_match = req.header("if-none-match").and_then(|v| v.as_str());
+    if if_none_match == Some(etag.as_str()) {
+        return Ok(Response::builder()
+            .status(304)
+            .header("etag", etag)
+            .header("cache-control", cache_control)
+            .header("vary", "accept")
+            .build());
+    }
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", format.content_type())
+        .header("etag", etag)
+        .header("cache-control", cache_control)
+        .header("vary", "accept")
+        .body(body)
+        .build())
+}
+
+/// Stations listed in a variable as `<provider>:<id>` or URLs.
+fn stations_variable(name: &str) -> Vec<String> {
+    spin_sdk::variables::get(name)
+        .unwrap_or_default()
+        .split([',', ' ', '\n'])
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+async fn handle_metrics(req: Request, _: Params) -> anyhow::Result<Response> {
+    let principal = match auth::authorize(&req, Scope::Read)? {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
+    log::info!("Authorized as {}", principal.name());
+
+    let mut stations = Vec::new();
+    let mut urls = Vec::new();
+    for station in stations_variable("metrics_stations") {
+        match resolve_station(&station) {
+            Some(url) => {
+                urls.push(url);
+                stations.push(station);
+            }
+            None => log::warn!("Invalid metrics station, skipping: {}", station),
+        }
+    }
+    if urls.len() > MAX_NUMBER_OF_MEASUREMENTS {
+        log::warn!(
+            "Too many metrics stations: {} (max: {}), skipping the rest",
+            urls.len(),
+            MAX_NUMBER_OF_MEASUREMENTS
+        );
+        urls.truncate(MAX_NUMBER_OF_MEASUREMENTS);
+        stations.truncate(MAX_NUMBER_OF_MEASUREMENTS);
+    }
+
+    let measurements = collect_measurements(&urls, &Fields::default()).await;
+    let providers = provider_names()
+        .into_iter()
+        .map(|provider| Ok((provider, health::counters(provider)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", metrics::CONTENT_TYPE)
+        .header("cache-control", "no-store")
+        .body(metrics::render(&stations, &measurements, &providers))
+        .build())
+}
+
+// Called by the poller component, stations with fresh readings in the cache are not fetched.
+async fn handle_poll(req: Request, _: Params) -> anyhow::Result<Response> {
+    if let Err(resp) = auth::authorize(&req, Scope::Admin)? {
+        return Ok(resp);
+    };
+
+    let urls = stations_variable("poll_stations")
+        .into_iter()
+        .filter_map(|station| {
+            let url = resolve_station(&station);
+            if url.is_none() {
+                log::warn!("Invalid poll station, skipping: {}", station);
+            }
+            url
+        })
+        .collect::<Vec<_>>();
+
+    let measurements = collect_measurements(&urls, &Fields::default()).await;
+    let updated = measurements
+        .iter()
+        .filter(|m| !m["update_time"].is_null())
+        .count();
 
     let data = json!({
-        "measurements": measurements,
-        "units": get_units(),
+        "stations": urls.len(),
+        "updated": updated,
     });
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(data.to_string())
+        .build())
+}
+
+fn handle_history(req: Request, _: Params) -> anyhow::Result<Response> {
+    if let Err(resp) = auth::authorize(&req, Scope::Read)? {
+        return Ok(resp);
+    };
 
+    let query: HashMap<_, _> = url::form_urlencoded::parse(req.query().as_bytes())
+        .into_owned()
+        .collect();
+    let station = query.get("station").map(|s| s.as_str()).unwrap_or_default();
+    let Some(url) = resolve_station(station) else {
+        return Ok(plain_text_resp(400, &format!("Invalid station: {}", station)));
+    };
+    let days = query
+        .get("days")
+        .and_then(|d| d.parse::<i64>().ok())
+        .unwrap_or(1);
+
+    let data = json!({
+        "station": url,
+        "measurements": history::read(&url, days)?,
+        "units": get_units(),
+    });
     Ok(Response::builder()
         .status(200)
         .header("content-type", "application/json")
@@ -139,21 +953,90 @@ async fn handle_post(req: &Request) -> anyhow::Result<Response> {
         .build())
 }
 
+/// Stores readings pushed by a station, authenticated with the key registered for it.
+fn ingest_upload(
+    id: &str,
+    key: &str,
+    params: &HashMap<String, String>,
+) -> anyhow::Result<Response> {
+    if let Err(e) = ingest::validate_station_id(id) {
+        return Ok(plain_text_resp(400, &e));
+    }
+    if !auth::verify_station(id, key)? {
+        log::error!("Invalid key of station: {}", id);
+        return Ok(plain_text_resp(401, "Invalid station key"));
+    }
+
+    let measurements = match ingest::parse_upload(params, chrono::Utc::now().naive_utc()) {
+        Ok(measurements) => measurements,
+        Err(e) => return Ok(plain_text_resp(400, &e.to_string())),
+    };
+    ingest::save(id, &measurements)?;
+
+    // pushed readings count as fresh ones, as if the station had been fetched
+    let url = LocalDownloader {}.station_url(id);
+    alerts::record(&url, &measurements);
+    if let Err(e) = history::append(&url, &measurements) {
+        log::error!("{} while saving history of: {}", e, url);
+    }
+    log::info!("Readings pushed by station: {}", id);
+    // stations using the Wunderground protocol expect exactly this
+    Ok(plain_text_resp(200, "success"))
+}
+
+// Wunderground `updateweatherstation.php` protocol, the station is given by `ID` and `PASSWORD`
+fn handle_ingest_wunderground(req: Request, _: Params) -> anyhow::Result<Response> {
+    let params: HashMap<_, _> = url::form_urlencoded::parse(req.query().as_bytes())
+        .into_owned()
+        .collect();
+    let id = params.get("ID").cloned().unwrap_or_default();
+    let key = params.get("PASSWORD").cloned().unwrap_or_default();
+    ingest_upload(&id, &key, &params)
+}
+
+// Ecowitt custom server protocol, the form carries the `PASSKEY` of the device
+fn handle_ingest_ecowitt(req: Request, params: Params) -> anyhow::Result<Response> {
+    let form: HashMap<_, _> = url::form_urlencoded::parse(req.body())
+        .into_owned()
+        .collect();
+    let id = params.get("station").unwrap_or_default();
+    let key = form.get("PASSKEY").cloned().unwrap_or_default();
+    ingest_upload(id, &key, &form)
+}
+
 #[http_component]
 async fn handle_weather_data_provider(req: Request) -> anyhow::Result<impl IntoResponse> {
     simple_logger::init_with_level(log::Level::Info)?;
-    log_req_info(&req)?;
-
-    match req.method() {
-        spin_sdk::http::Method::Get => {
-            let app_name = env!("CARGO_PKG_NAME");
-            let app_version = env!("CARGO_PKG_VERSION");
-            Ok(plain_text_resp(
-                200,
-                &format!("Hello from {app_name} v{app_version}"),
-            ))
-        }
-        spin_sdk::http::Method::Post => handle_post(&req).await,
-        _ => Ok(plain_text_resp(405, "Method not allowed")),
+    let client_id = log_req_info(&req)?;
+
+    if let Some(resp) = check_rate_limit(&req, &client_id)? {
+        stats::flush()?;
+        return Ok(resp);
+    }
+
+    let mut router = Router::default();
+
+    router.get("/api/v1", handle_get);
+    router.post_async("/api/v1", handle_post);
+    router.post("/api/v1/session", handle_session);
+    router.get("/api/v1/stats", handle_stats);
+    router.get("/api/v1/health", handle_health);
+    router.get_async("/api/v1/measurements", handle_get_measurements);
+    router.get_async("/api/v1/metrics", handle_metrics);
+    router.post_async("/api/v1/poll", handle_poll);
+    router.get("/api/v1/history", handle_history);
+    router.get("/api/v1/alerts", handle_list_alerts);
+    router.put("/api/v1/alerts/:name", handle_put_alert);
+    router.delete("/api/v1/alerts/:name", handle_delete_alert);
+    router.get("/api/v1/ingest/wunderground", handle_ingest_wunderground);
+    router.post("/api/v1/ingest/ecowitt/:station", handle_ingest_ecowitt);
+
+    let resp = router.handle_async(req).await;
+    stats::flush()?;
+    health::flush()?;
+    // alerts must not fail the request whose measurements triggered them
+    if let Err(e) = alerts::flush().await {
+        log::error!("Alerts not checked: {}", e);
     }
+    Ok(resp)
 }
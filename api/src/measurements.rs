@@ -1,15 +1,46 @@
 use serde_json::json;
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
 
-#[derive(serde::Serialize, Default, Debug)]
+// forecast keys are `forecast_<key>_+<hours>h`
+pub const FORECAST_HOURS: &[i64] = &[1, 3, 6];
+const FORECAST_QUANTITIES: &[&str] = &["wind_speed", "gusts_speed", "temperature"];
+
+// wind window keys are `wind_speed_<stat>_<minutes>m`
+const WINDOW_STATS: &[&str] = &["min", "avg", "max"];
+const DEFAULT_WIND_WINDOWS: &[i64] = &[10, 30, 60];
+const MAX_WIND_WINDOW_MINUTES: i64 = 180;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default, Debug)]
 pub struct Measurements {
     pub update_time: Option<String>,
     pub humidity: Option<u64>,
     pub precipitation: Option<f64>,
     pub pressure: Option<u64>,
     pub temperature: Option<f64>,
+    /// Daily extremes, since midnight at the station
+    pub temperature_max: Option<f64>,
+    pub temperature_min: Option<f64>,
     pub wind_direction: Option<String>,
     pub wind_speed: Option<u64>,
     pub gusts_speed: Option<u64>,
+    /// Daily maximum of the gusts
+    pub gusts_speed_max: Option<u64>,
+    /// Lulls, the minimum wind speed
+    pub wind_speed_min: Option<u64>,
+    pub dew_point: Option<f64>,
+    pub pressure_sea_level: Option<u64>,
+    /// `online`, `offline` or `outdated`, for providers that report the state of the station
+    pub status: Option<String>,
+    /// Global solar radiation in W/m²
+    pub solar_radiation: Option<u64>,
+    pub uv_index: Option<f64>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Keys returned only when requested by name: forecasts for the coordinates of the station,
+    /// e.g. `forecast_wind_speed_+3h`, and wind over recent windows, e.g. `wind_speed_max_30m`
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
 }
 
 pub fn get_units() -> serde_json::Value {
@@ -19,9 +50,223 @@ pub fn get_units() -> serde_json::Value {
         "precipitation": "mm",
         "pressure": "hPa",
         "temperature": "\u{00B0}C",
+        "temperature_max": "\u{00B0}C",
+        "temperature_min": "\u{00B0}C",
         "wind_direction": "",
         "wind_speed": "km/h",
         "gusts_speed": "km/h",
+        "gusts_speed_max": "km/h",
+        "wind_speed_min": "km/h",
+        "dew_point": "\u{00B0}C",
+        "pressure_sea_level": "hPa",
+        "status": "",
+        "solar_radiation": "W/m\u{00B2}",
+        "uv_index": "",
+        "latitude": "\u{00B0}",
+        "longitude": "\u{00B0}",
     });
     units
 }
+
+pub fn forecast_key(quantity: &str, hours: i64) -> String {
+    format!("forecast_{}_+{}h", quantity, hours)
+}
+
+pub fn is_forecast_key(key: &str) -> bool {
+    forecast_units().get(key).is_some()
+}
+
+/// Units of the forecast keys, these are left out unless requested explicitly
+pub fn forecast_units() -> serde_json::Value {
+    let units = get_units();
+    let mut forecast = serde_json::Map::new();
+    for quantity in FORECAST_QUANTITIES {
+        for hours in FORECAST_HOURS {
+            forecast.insert(forecast_key(quantity, *hours), units[quantity].clone());
+        }
+    }
+    serde_json::Value::Object(forecast)
+}
+
+thread_local! {
+    // instances serve a single request, so the variable is read once per request
+    static WIND_WINDOWS: OnceCell<Vec<i64>> = const { OnceCell::new() };
+}
+
+/// Windows of the wind statistics in minutes, from the `wind_windows_minutes` variable
+pub fn wind_windows() -> Vec<i64> {
+    WIND_WINDOWS.with(|windows| windows.get_or_init(read_wind_windows).clone())
+}
+
+fn read_wind_windows() -> Vec<i64> {
+    let windows = spin_sdk::variables::get("wind_windows_minutes")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|w| w.trim().parse::<i64>().ok())
+        .filter(|w| (1..=MAX_WIND_WINDOW_MINUTES).contains(w))
+        .collect::<Vec<_>>();
+    if windows.is_empty() {
+        DEFAULT_WIND_WINDOWS.to_vec()
+    } else {
+        windows
+    }
+}
+
+pub fn window_key(stat: &str, minutes: i64) -> String {
+    format!("wind_speed_{}_{}m", stat, minutes)
+}
+
+pub fn is_window_key(key: &str) -> bool {
+    let Some((stat, minutes)) = key
+        .strip_prefix("wind_speed_")
+        .and_then(|rest| rest.strip_suffix('m'))
+        .and_then(|rest| rest.split_once('_'))
+    else {
+        return false;
+    };
+    let Ok(minutes) = minutes.parse::<i64>() else {
+        return false;
+    };
+    // e.g. `wind_speed_max_030m` is not a key
+    WINDOW_STATS.contains(&stat)
+        && window_key(stat, minutes) == key
+        && wind_windows().contains(&minutes)
+}
+
+/// Units of the wind window keys, these are left out unless requested explicitly
+pub fn window_units() -> serde_json::Value {
+    let mut windows = serde_json::Map::new();
+    for minutes in wind_windows() {
+        for stat in WINDOW_STATS {
+            windows.insert(window_key(stat, minutes), json!("km/h"));
+        }
+    }
+    serde_json::Value::Object(windows)
+}
+
+// units of the keys returned only when requested by name
+fn extra_units() -> serde_json::Map<String, serde_json::Value> {
+    let mut units = serde_json::Map::new();
+    for extra in [forecast_units(), window_units()] {
+        if let serde_json::Value::Object(extra) = extra {
+            units.extend(extra);
+        }
+    }
+    units
+}
+
+/// Unit of any valid key, `None` for unknown keys
+pub fn unit_for_key(key: &str) -> Option<serde_json::Value> {
+    let units = get_units();
+    match units.get(key) {
+        Some(unit) => Some(unit.clone()),
+        None => extra_units().get(key).cloned(),
+    }
+}
+
+/// Dew point in °C, from the Magnus formula with the constants of Sonntag (1990).
+pub fn dew_point(temperature: f64, humidity: f64) -> f64 {
+    const B: f64 = 17.62;
+    const C: f64 = 243.12;
+    let gamma = (humidity.clamp(1.0, 100.0) / 100.0).ln() + B * temperature / (C + temperature);
+    C * gamma / (B - gamma)
+}
+
+/// Measurement keys requested by the client, all of them when not specified.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct Fields(Option<Vec<String>>);
+
+impl Fields {
+    pub fn new(keys: Vec<String>) -> Self {
+        if keys.is_empty() {
+            Fields(None)
+        } else {
+            Fields(Some(keys))
+        }
+    }
+
+    /// Adds keys needed regardless of what was requested
+    pub fn with(self, keys: &[&str]) -> Self {
+        match self.0 {
+            Some(mut requested) => {
+                requested.extend(keys.iter().map(|k| k.to_string()));
+                Fields(Some(requested))
+            }
+            None => Fields(None),
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        match &self.0 {
+            Some(keys) => keys.iter().any(|k| k == key),
+            None => true,
+        }
+    }
+
+    /// Whether all keys of `other` are among these ones
+    pub fn covers(&self, other: &Fields) -> bool {
+        // forecast and window keys are not among all keys, only when asked for by name
+        let covers = |k: &String| {
+            if is_forecast_key(k) || is_window_key(k) {
+                self.requests(k)
+            } else {
+                self.contains(k)
+            }
+        };
+        match &other.0 {
+            Some(keys) => keys.iter().all(covers),
+            None => self.is_all(),
+        }
+    }
+
+    /// Whether the key was asked for by name, not only as one of all keys
+    pub fn requests(&self, key: &str) -> bool {
+        match &self.0 {
+            Some(keys) => keys.iter().any(|k| k == key),
+            None => false,
+        }
+    }
+
+    pub fn contains_any(&self, keys: &[&str]) -> bool {
+        keys.iter().any(|k| self.contains(k))
+    }
+
+    pub fn requests_forecast(&self) -> bool {
+        match &self.0 {
+            Some(keys) => keys.iter().any(|k| is_forecast_key(k)),
+            None => false,
+        }
+    }
+
+    pub fn requests_windows(&self) -> bool {
+        match &self.0 {
+            Some(keys) => keys.iter().any(|k| is_window_key(k)),
+            None => false,
+        }
+    }
+
+    /// Units of the requested keys
+    pub fn units(&self) -> serde_json::Value {
+        let mut units = self.select(get_units());
+        if let serde_json::Value::Object(units) = &mut units {
+            let extra = extra_units().into_iter();
+            units.extend(extra.filter(|(key, _)| self.requests(key)));
+        }
+        units
+    }
+
+    /// Keeps only the requested keys of a JSON object, e.g. of serialized `Measurements`.
+    pub fn select(&self, value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(mut map) => {
+                map.retain(|key, _| self.contains(key));
+                serde_json::Value::Object(map)
+            }
+            other => other,
+        }
+    }
+}
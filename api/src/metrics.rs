@@ -0,0 +1,205 @@
+use crate::collectors::common::wind_direction_degrees;
+use crate::health::Counters;
+use chrono::NaiveDateTime;
+use serde_json::Value;
+use std::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+struct Gauge {
+    key: &'static str,
+    name: &'static str,
+    unit: &'static str,
+    help: &'static str,
+}
+
+// measurement keys exposed as gauges, the name has to end with the unit
+const GAUGES: &[Gauge] = &[
+    Gauge {
+        key: "update_time",
+        name: "weather_update_timestamp_seconds",
+        unit: "seconds",
+        help: "Time of the last update of the readings",
+    },
+    Gauge {
+        key: "humidity",
+        name: "weather_humidity_percent",
+        unit: "percent",
+        help: "Relative humidity",
+    },
+    Gauge {
+        key: "precipitation",
+        name: "weather_precipitation_mm",
+        unit: "mm",
+        help: "Precipitation",
+    },
+    Gauge {
+        key: "pressure",
+        name: "weather_pressure_hpa",
+        unit: "hpa",
+        help: "Atmospheric pressure",
+    },
+    Gauge {
+        key: "pressure_sea_level",
+        name: "weather_pressure_sea_level_hpa",
+        unit: "hpa",
+        help: "Atmospheric pressure reduced to sea level",
+    },
+    Gauge {
+        key: "temperature",
+        name: "weather_temperature_celsius",
+        unit: "celsius",
+        help: "Air temperature",
+    },
+    Gauge {
+        key: "dew_point",
+        name: "weather_dew_point_celsius",
+        unit: "celsius",
+        help: "Dew point",
+    },
+    Gauge {
+        key: "wind_direction",
+        name: "weather_wind_direction_degrees",
+        unit: "degrees",
+        help: "Wind direction, center of the compass sector",
+    },
+    Gauge {
+        key: "wind_speed",
+        name: "weather_wind_speed_kmh",
+        unit: "kmh",
+        help: "Average wind speed",
+    },
+    Gauge {
+        key: "gusts_speed",
+        name: "weather_gusts_speed_kmh",
+        unit: "kmh",
+        help: "Wind gusts speed",
+    },
+    Gauge {
+        key: "wind_speed_min",
+        name: "weather_wind_speed_min_kmh",
+        unit: "kmh",
+        help: "Minimum wind speed, lulls",
+    },
+    Gauge {
+        key: "solar_radiation",
+        name: "weather_solar_radiation_watts_per_square_meter",
+        unit: "watts_per_square_meter",
+        help: "Global solar radiation",
+    },
+    Gauge {
+        key: "uv_index",
+        name: "weather_uv_index",
+        unit: "",
+        help: "UV index",
+    },
+];
+
+struct HealthFamily {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&Counters) -> u64,
+}
+
+const HEALTH_FAMILIES: &[HealthFamily] = &[
+    HealthFamily {
+        name: "weather_collector_successes",
+        kind: "counter",
+        help: "Successful downloads",
+        value: |c| c.successes,
+    },
+    HealthFamily {
+        name: "weather_collector_failures",
+        kind: "counter",
+        help: "Failed downloads",
+        value: |c| c.failures,
+    },
+    HealthFamily {
+        name: "weather_collector_failure_streak",
+        kind: "gauge",
+        help: "Consecutive failed downloads",
+        value: |c| c.failure_streak,
+    },
+    HealthFamily {
+        name: "weather_collector_up",
+        kind: "gauge",
+        help: "Whether the provider is not considered broken",
+        value: |c| u64::from(!c.broken),
+    },
+];
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn sample_value(key: &str, value: &Value) -> Option<f64> {
+    match key {
+        "update_time" => NaiveDateTime::parse_from_str(value.as_str()?, "%Y-%m-%d %H:%M")
+            .ok()
+            .map(|t| t.and_utc().timestamp() as f64),
+        "wind_direction" => wind_direction_degrees(value.as_str()?),
+        _ => value.as_f64(),
+    }
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, unit: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    if !unit.is_empty() {
+        let _ = writeln!(out, "# UNIT {} {}", name, unit);
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+}
+
+/// Renders readings of the stations and health of the providers in the OpenMetrics text format.
+///
+/// Readings that are not available are left out instead of being reported as NaN.
+pub fn render(
+    stations: &[String],
+    measurements: &[Value],
+    providers: &[(&str, Counters)],
+) -> String {
+    let mut out = String::new();
+
+    for gauge in GAUGES {
+        write_family(&mut out, gauge.name, "gauge", gauge.unit, gauge.help);
+        for (station, m) in stations.iter().zip(measurements) {
+            let Some(value) = m.get(gauge.key).and_then(|v| sample_value(gauge.key, v)) else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "{}{{station=\"{}\"}} {}",
+                gauge.name,
+                escape_label(station),
+                value
+            );
+        }
+    }
+
+    for family in HEALTH_FAMILIES {
+        write_family(&mut out, family.name, family.kind, "", family.help);
+        // samples of counters carry the suffix, the family name does not
+        let suffix = if family.kind == "counter" {
+            "_total"
+        } else {
+            ""
+        };
+        for (provider, counters) in providers {
+            let _ = writeln!(
+                out,
+                "{}{}{{provider=\"{}\"}} {}",
+                family.name,
+                suffix,
+                escape_label(provider),
+                (family.value)(counters)
+            );
+        }
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use spin_sdk::http::Response;
+use spin_sdk::key_value::Store;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STORE_NAME: &str = "stats";
+const KEY_PREFIX: &str = "ratelimit:";
+
+const DEFAULT_CLIENT_PER_MINUTE: f64 = 30.0;
+const DEFAULT_CLIENT_BURST: f64 = 60.0;
+const DEFAULT_KEY_PER_MINUTE: f64 = 120.0;
+const DEFAULT_UPSTREAM_PER_MINUTE: f64 = 120.0;
+
+// buckets idle that long are full again, as good as missing, so these are removed
+const BUCKET_TTL_SECS: f64 = 3600.0;
+const PRUNE_INTERVAL_SECS: f64 = 3600.0;
+const PRUNED_AT_KEY: &str = "ratelimit_pruned_at";
+
+/// Token bucket parameters, `burst` is the capacity of the bucket.
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+    pub per_minute: f64,
+    pub burst: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: f64,
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+fn get_variable(name: &str, default: f64) -> f64 {
+    spin_sdk::variables::get(name)
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(default)
+}
+
+impl Limit {
+    pub fn per_client() -> Self {
+        Limit {
+            per_minute: get_variable("rate_limit_per_minute", DEFAULT_CLIENT_PER_MINUTE),
+            burst: get_variable("rate_limit_burst", DEFAULT_CLIENT_BURST),
+        }
+    }
+
+    pub fn per_key(per_minute: Option<f64>) -> Self {
+        let per_minute = per_minute.unwrap_or_else(|| {
+            get_variable("api_key_rate_limit_per_minute", DEFAULT_KEY_PER_MINUTE)
+        });
+        Limit {
+            per_minute,
+            burst: per_minute,
+        }
+    }
+
+    pub fn per_provider() -> Self {
+        let per_minute = get_variable("upstream_limit_per_minute", DEFAULT_UPSTREAM_PER_MINUTE);
+        Limit {
+            per_minute,
+            burst: per_minute,
+        }
+    }
+}
+
+/// Removes idle buckets, at most once per `PRUNE_INTERVAL_SECS`.
+fn prune(store: &Store, now: f64) -> anyhow::Result<()> {
+    let pruned_at = store.get_json::<f64>(PRUNED_AT_KEY)?.unwrap_or_default();
+    if now - pruned_at < PRUNE_INTERVAL_SECS {
+        return Ok(());
+    }
+    store.set_json(PRUNED_AT_KEY, &now)?;
+
+    for key in store.get_keys()? {
+        if !key.starts_with(KEY_PREFIX) {
+            continue;
+        }
+        match store.get_json::<Bucket>(&key) {
+            Ok(Some(bucket)) if now - bucket.updated < BUCKET_TTL_SECS => {}
+            _ => store.delete(&key)?,
+        }
+    }
+    Ok(())
+}
+
+/// Takes a token from the bucket identified by `key`.
+///
+/// Returns the number of seconds until a token is available when the bucket is empty.
+/// Buckets live in the key-value store, so concurrent instances may let a few extra
+/// requests through. This is fine for the purpose.
+pub fn take(key: &str, limit: Limit) -> anyhow::Result<Option<u64>> {
+    if limit.per_minute <= 0.0 {
+        return Ok(None);
+    }
+
+    let store = Store::open(STORE_NAME)?;
+    let key = format!("{}{}", KEY_PREFIX, key);
+    let now = now();
+    prune(&store, now)?;
+    let rate = limit.per_minute / 60.0;
+
+    let mut bucket = store.get_json::<Bucket>(&key)?.unwrap_or(Bucket {
+        tokens: limit.burst,
+        updated: now,
+    });
+    bucket.tokens = (bucket.tokens + (now - bucket.updated).max(0.0) * rate).min(limit.burst);
+    bucket.updated = now;
+
+    if bucket.tokens < 1.0 {
+        store.set_json(&key, &bucket)?;
+        return Ok(Some(((1.0 - bucket.tokens) / rate).ceil() as u64));
+    }
+    bucket.tokens -= 1.0;
+    store.set_json(&key, &bucket)?;
+    Ok(None)
+}
+
+pub fn too_many_requests_resp(retry_after: u64) -> Response {
+    Response::builder()
+        .status(429)
+        .header("content-type", "text/plain")
+        .header("retry-after", retry_after.to_string())
+        .body("Too many requests")
+        .build()
+}
@@ -0,0 +1,220 @@
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use spin_sdk::key_value::Store;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const STORE_NAME: &str = "stats";
+const KEY_PREFIX: &str = "usage:";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+// latency samples kept per provider and day, older ones are dropped first
+const MAX_LATENCY_SAMPLES: usize = 500;
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct FetchUsage {
+    fetches: u64,
+    errors: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct ProviderUsage {
+    fetches: u64,
+    errors: u64,
+    latencies_ms: Vec<u64>,
+}
+
+/// Usage of a single day, stored under `usage:<date>` in the `stats` store.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct DailyUsage {
+    requests: u64,
+    // anonymized client ID -> number of requests
+    clients: BTreeMap<String, u64>,
+    providers: BTreeMap<String, ProviderUsage>,
+    stations: BTreeMap<String, FetchUsage>,
+}
+
+impl DailyUsage {
+    fn merge(&mut self, other: DailyUsage) {
+        self.requests += other.requests;
+        for (client, count) in other.clients {
+            *self.clients.entry(client).or_default() += count;
+        }
+        for (name, usage) in other.providers {
+            let provider = self.providers.entry(name).or_default();
+            provider.fetches += usage.fetches;
+            provider.errors += usage.errors;
+            provider.latencies_ms.extend(usage.latencies_ms);
+            let excess = provider
+                .latencies_ms
+                .len()
+                .saturating_sub(MAX_LATENCY_SAMPLES);
+            provider.latencies_ms.drain(..excess);
+        }
+        for (url, usage) in other.stations {
+            let station = self.stations.entry(url).or_default();
+            station.fetches += usage.fetches;
+            station.errors += usage.errors;
+        }
+    }
+}
+
+thread_local! {
+    // usage collected while handling the current request
+    static PENDING: RefCell<DailyUsage> = RefCell::new(DailyUsage::default());
+}
+
+pub fn record_request(client_id: &str) {
+    PENDING.with_borrow_mut(|usage| {
+        usage.requests += 1;
+        *usage.clients.entry(client_id.to_owned()).or_default() += 1;
+    });
+}
+
+pub fn record_fetch(provider: &str, url: &str, latency: Duration, success: bool) {
+    let errors = if success { 0 } else { 1 };
+    PENDING.with_borrow_mut(|usage| {
+        let provider = usage.providers.entry(provider.to_owned()).or_default();
+        provider.fetches += 1;
+        provider.errors += errors;
+        provider.latencies_ms.push(latency.as_millis() as u64);
+
+        let station = usage.stations.entry(url.to_owned()).or_default();
+        station.fetches += 1;
+        station.errors += errors;
+    });
+}
+
+fn retention_days() -> i64 {
+    spin_sdk::variables::get("stats_retention_days")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+// Older versions counted requests under the bare client address, a key without
+// any prefix holding a plain decimal count. Every other key of the store has a
+// `<kind>:` prefix, or is a JSON value like the rate limiter's bookkeeping.
+fn is_legacy_counter(store: &Store, key: &str) -> anyhow::Result<bool> {
+    if key.contains(':') {
+        return Ok(false);
+    }
+    let value = store.get(key)?.unwrap_or_default();
+    Ok(!value.is_empty() && value.iter().all(u8::is_ascii_digit))
+}
+
+fn prune(store: &Store, today: NaiveDate) -> anyhow::Result<()> {
+    let oldest = today - chrono::Duration::days(retention_days());
+    for key in store.get_keys()? {
+        let Some(date) = key.strip_prefix(KEY_PREFIX) else {
+            if is_legacy_counter(store, &key)? {
+                store.delete(&key)?;
+            }
+            continue;
+        };
+        match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+            Ok(date) if date >= oldest => {}
+            _ => store.delete(&key)?,
+        }
+    }
+    Ok(())
+}
+
+/// Adds usage collected while handling the current request to the stored one.
+pub fn flush() -> anyhow::Result<()> {
+    let pending = PENDING.take();
+    if pending.requests == 0 && pending.providers.is_empty() {
+        return Ok(());
+    }
+
+    let today = Utc::now().date_naive();
+    let key = format!("{}{}", KEY_PREFIX, today.format(DATE_FORMAT));
+    let store = Store::open(STORE_NAME)?;
+
+    // read-modify-write, concurrent requests may occasionally lose an update
+    let usage = match store.get_json::<DailyUsage>(&key)? {
+        Some(mut usage) => {
+            usage.merge(pending);
+            usage
+        }
+        None => {
+            prune(&store, today)?;
+            pending
+        }
+    };
+    store.set_json(&key, &usage)?;
+    Ok(())
+}
+
+fn percentile(sorted: &[u64], p: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted.get(idx).copied()
+}
+
+fn error_rate(fetches: u64, errors: u64) -> Option<f64> {
+    (fetches > 0).then(|| errors as f64 / fetches as f64)
+}
+
+/// Summary of the usage over the last `days` days, keyed by date.
+pub fn report(days: i64) -> anyhow::Result<serde_json::Value> {
+    let store = Store::open(STORE_NAME)?;
+    let today = Utc::now().date_naive();
+
+    let mut report = serde_json::Map::new();
+    for offset in 0..days.clamp(1, retention_days().max(1)) {
+        let date = (today - chrono::Duration::days(offset))
+            .format(DATE_FORMAT)
+            .to_string();
+        let Some(usage) = store.get_json::<DailyUsage>(format!("{}{}", KEY_PREFIX, date))? else {
+            continue;
+        };
+
+        let providers = usage
+            .providers
+            .into_iter()
+            .map(|(name, mut provider)| {
+                provider.latencies_ms.sort_unstable();
+                let latency = &provider.latencies_ms;
+                let summary = json!({
+                    "fetches": provider.fetches,
+                    "errors": provider.errors,
+                    "error_rate": error_rate(provider.fetches, provider.errors),
+                    "latency_ms": {
+                        "p50": percentile(latency, 0.5),
+                        "p90": percentile(latency, 0.9),
+                        "p99": percentile(latency, 0.99),
+                    },
+                });
+                (name, summary)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        let stations = usage
+            .stations
+            .into_iter()
+            .map(|(url, station)| {
+                let summary = json!({
+                    "fetches": station.fetches,
+                    "errors": station.errors,
+                    "error_rate": error_rate(station.fetches, station.errors),
+                });
+                (url, summary)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        let summary = json!({
+            "requests": usage.requests,
+            "clients": usage.clients.len(),
+            "providers": providers,
+            "stations": stations,
+        });
+        report.insert(date, summary);
+    }
+    Ok(serde_json::Value::Object(report))
+}
@@ -1,10 +1,350 @@
+use serde::{Deserialize, Serialize};
 use spin_sdk::http::{IntoResponse, Method, Params, Request, Response, Router};
 use spin_sdk::http_component;
+use spin_sdk::key_value::Store;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const LESMA_BASE_URL: &str = "https://lesma.eu";
 
-async fn handle_get(_: Request, params: Params) -> anyhow::Result<Response> {
-    let id = params.get("id").unwrap_or_default();
+const STORE_NAME: &str = "configs";
+const DEFAULT_EXPIRE_HOURS: u64 = 24;
+const MAX_PAYLOAD_SIZE: usize = 16 * 1024;
+const MAX_STATIONS: usize = 50;
+const MAX_MEASUREMENTS: usize = 20;
+const MAX_FIELD_LENGTH: usize = 512;
+
+// stations given as `<provider>:<id>`, the same providers as accepted by the API
+const STATION_PROVIDERS: &[&str] = &[
+    "aemet",
+    "meteocat",
+    "meteoclimatic",
+    "weatherlink",
+    "weatherlink_v2",
+    "openwindmap",
+    "metno",
+    "holfuy",
+    "windguru",
+    "wunderground",
+    "ambient",
+    "local",
+];
+
+const RATE_LIMIT_WINDOW_SECS: u64 = 3600;
+const DEFAULT_POST_LIMIT: u64 = 20;
+const DEFAULT_GET_LIMIT: u64 = 300;
+
+// IDs must match `build_config_id_regex` in the UI, i.e. `[a-z]+`
+const ID_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const ID_LENGTH: usize = 8;
+const ID_MAX_ATTEMPTS: usize = 5;
+// IDs of lesma.eu are lowercase letters too, their length is not fixed
+const MAX_LESMA_ID_LENGTH: usize = 32;
+
+const RATE_LIMIT_PREFIX: &str = "ratelimit:";
+// expired configs and rate limit windows are removed at most that often
+const PRUNE_INTERVAL_SECS: u64 = 3600;
+const PRUNED_AT_KEY: &str = "pruned_at";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Backend {
+    Kv,
+    Lesma,
+}
+
+impl Backend {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "kv" => Ok(Backend::Kv),
+            "lesma" => Ok(Backend::Lesma),
+            _ => anyhow::bail!("Unsupported backend: {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Expiry {
+    Hours(u64),
+    Never,
+}
+
+impl Expiry {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "never" => Ok(Expiry::Never),
+            hours => match hours.parse::<u64>() {
+                Ok(0) | Err(_) => anyhow::bail!("Invalid expiry: {}", value),
+                Ok(hours) => Ok(Expiry::Hours(hours)),
+            },
+        }
+    }
+
+    fn expires_at(&self) -> Option<u64> {
+        match self {
+            Expiry::Hours(hours) => Some(now() + hours * 3600),
+            Expiry::Never => None,
+        }
+    }
+}
+
+// Mirrors `Config` of the UI, anything else is not accepted
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfigStation {
+    label: String,
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfigMeasurement {
+    label: String,
+    key: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    stations: Vec<ConfigStation>,
+    measurements: Vec<ConfigMeasurement>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RateLimitWindow {
+    start: u64,
+    count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredConfig {
+    content: String,
+    // seconds since the UNIX epoch, `None` for permanent links
+    expires_at: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn get_query(req: &Request) -> HashMap<String, String> {
+    url::form_urlencoded::parse(req.query().as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn get_variable(name: &str) -> Option<String> {
+    spin_sdk::variables::get(name)
+        .ok()
+        .filter(|v| !v.is_empty())
+}
+
+fn default_backend() -> anyhow::Result<Backend> {
+    get_variable("backend").map_or(Ok(Backend::Kv), |v| Backend::parse(&v))
+}
+
+fn default_expiry() -> anyhow::Result<Expiry> {
+    get_variable("expire_hours").map_or(Ok(Expiry::Hours(DEFAULT_EXPIRE_HOURS)), |v| {
+        Expiry::parse(&v)
+    })
+}
+
+fn generate_id() -> anyhow::Result<String> {
+    // reject bytes that would bias the distribution over the alphabet
+    let limit = 256 - 256 % ID_ALPHABET.len();
+    let mut id = String::with_capacity(ID_LENGTH);
+    let mut buf = [0u8; 32];
+    while id.len() < ID_LENGTH {
+        getrandom::getrandom(&mut buf).map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        for b in buf.iter().map(|&b| b as usize).filter(|&b| b < limit) {
+            if id.len() == ID_LENGTH {
+                break;
+            }
+            id.push(ID_ALPHABET[b % ID_ALPHABET.len()] as char);
+        }
+    }
+    Ok(id)
+}
+
+fn get_client_addr(req: &Request) -> String {
+    let client_addr = req
+        .header("spin-client-addr")
+        .and_then(|v| v.as_str())
+        .unwrap_or("?");
+    // drop the port
+    match client_addr.rsplit_once(':') {
+        Some((addr, _)) => addr.to_string(),
+        None => client_addr.to_string(),
+    }
+}
+
+fn get_limit(name: &str, default: u64) -> u64 {
+    get_variable(name)
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
+// Fixed window limiter, returns the number of seconds to wait when exceeded
+fn check_rate_limit(req: &Request, limit: u64) -> anyhow::Result<Option<u64>> {
+    let store = Store::open(STORE_NAME)?;
+    let key = format!("{}{}:{}", RATE_LIMIT_PREFIX, req.method(), get_client_addr(req));
+    let now = now();
+    prune(&store, now)?;
+
+    let mut window = store
+        .get_json::<RateLimitWindow>(&key)?
+        .filter(|w| w.start + RATE_LIMIT_WINDOW_SECS > now)
+        .unwrap_or(RateLimitWindow {
+            start: now,
+            count: 0,
+        });
+
+    if window.count >= limit {
+        return Ok(Some(window.start + RATE_LIMIT_WINDOW_SECS - now));
+    }
+    window.count += 1;
+    store.set_json(&key, &window)?;
+    Ok(None)
+}
+
+/// Removes expired configs and rate limit windows, at most once per `PRUNE_INTERVAL_SECS`.
+fn prune(store: &Store, now: u64) -> anyhow::Result<()> {
+    let pruned_at = store.get_json::<u64>(PRUNED_AT_KEY)?.unwrap_or_default();
+    if now < pruned_at + PRUNE_INTERVAL_SECS {
+        return Ok(());
+    }
+    store.set_json(PRUNED_AT_KEY, &now)?;
+
+    for key in store.get_keys()? {
+        let expired = if key.starts_with(RATE_LIMIT_PREFIX) {
+            match store.get_json::<RateLimitWindow>(&key) {
+                Ok(Some(window)) => window.start + RATE_LIMIT_WINDOW_SECS <= now,
+                _ => true,
+            }
+        } else if is_valid_id(&key, ID_LENGTH) {
+            match store.get_json::<StoredConfig>(&key) {
+                Ok(Some(stored)) => stored.expires_at.is_some_and(|t| t <= now),
+                _ => true,
+            }
+        } else {
+            false
+        };
+        if expired {
+            store.delete(&key)?;
+        }
+    }
+    Ok(())
+}
+
+fn rate_limited_resp(retry_after: u64) -> Response {
+    Response::builder()
+        .status(429)
+        .header("Retry-After", retry_after.to_string())
+        .body("Too many requests")
+        .build()
+}
+
+/// Whether the ID is made of lowercase letters, as generated here and by lesma.eu
+fn is_valid_id(id: &str, max_length: usize) -> bool {
+    (1..=max_length).contains(&id.len()) && id.bytes().all(|b| ID_ALPHABET.contains(&b))
+}
+
+/// Whether the station is a URL or `<provider>:<id>`, as the API accepts them
+fn is_valid_station(station: &str) -> bool {
+    let url = station
+        .strip_prefix("https://")
+        .or_else(|| station.strip_prefix("http://"));
+    if let Some(rest) = url {
+        return !rest.is_empty();
+    }
+    match station.split_once(':') {
+        Some((provider, id)) => {
+            STATION_PROVIDERS.contains(&provider.to_lowercase().as_str()) && !id.is_empty()
+        }
+        None => false,
+    }
+}
+
+fn validate_config(content: &str) -> anyhow::Result<()> {
+    // the same way as `download_config` of the UI does it
+    let config_bare = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("");
+    let config: Config = serde_json::from_str(&config_bare)?;
+
+    if config.stations.len() > MAX_STATIONS {
+        anyhow::bail!("Too many stations: {}", config.stations.len());
+    }
+    if config.measurements.len() > MAX_MEASUREMENTS {
+        anyhow::bail!("Too many measurements: {}", config.measurements.len());
+    }
+    for station in config.stations.iter() {
+        if !is_valid_station(&station.url) {
+            anyhow::bail!("Invalid station URL: {}", station.url);
+        }
+    }
+    let fields = config
+        .stations
+        .iter()
+        .flat_map(|s| [&s.label, &s.url])
+        .chain(config.measurements.iter().flat_map(|m| [&m.label, &m.key]));
+    for field in fields {
+        if field.len() > MAX_FIELD_LENGTH {
+            anyhow::bail!("Field too long: {} bytes", field.len());
+        }
+    }
+    Ok(())
+}
+
+fn validate_payload(body: &[u8]) -> Result<String, Response> {
+    if body.is_empty() {
+        return Err(Response::new(400, "Empty payload"));
+    }
+    if body.len() > MAX_PAYLOAD_SIZE {
+        return Err(Response::new(413, "Payload too large"));
+    }
+    let content = String::from_utf8(body.to_vec())
+        .map_err(|_| Response::new(400, "Payload is not valid UTF-8"))?;
+    validate_config(&content)
+        .map_err(|e| Response::new(400, format!("Payload is not a valid config: {}", e)))?;
+    Ok(content)
+}
+
+fn get_from_kv(id: &str) -> anyhow::Result<Option<String>> {
+    let store = Store::open(STORE_NAME)?;
+    let Some(stored) = store.get_json::<StoredConfig>(id)? else {
+        return Ok(None);
+    };
+
+    if stored.expires_at.is_some_and(|t| t <= now()) {
+        store.delete(id)?;
+        return Ok(None);
+    }
+    Ok(Some(stored.content))
+}
+
+fn post_to_kv(content: String, expiry: Expiry) -> anyhow::Result<String> {
+    let store = Store::open(STORE_NAME)?;
+    for _ in 0..ID_MAX_ATTEMPTS {
+        let id = generate_id()?;
+        if !store.exists(&id)? {
+            let stored = StoredConfig {
+                content,
+                expires_at: expiry.expires_at(),
+            };
+            store.set_json(&id, &stored)?;
+            return Ok(id);
+        }
+    }
+    anyhow::bail!("Failed to generate a unique ID");
+}
+
+async fn get_from_lesma(id: &str) -> anyhow::Result<Response> {
     let url = format!("{}/{}?raw", LESMA_BASE_URL, id);
     let request = Request::builder().method(Method::Get).uri(url).build();
 
@@ -13,19 +353,22 @@ async fn handle_get(_: Request, params: Params) -> anyhow::Result<Response> {
     let body_str = String::from_utf8(body_bytes.to_vec())?;
     let status = *resp.status();
 
-    if status == 200 {
-        Ok(Response::new(status, body_str.as_str()))
-    } else {
-        Ok(Response::new(status, "Error from lesma.eu".to_string()))
+    if status != 200 {
+        return Ok(Response::new(status, "Error from lesma.eu".to_string()));
     }
+    if body_str.len() > MAX_PAYLOAD_SIZE || validate_config(&body_str).is_err() {
+        return Ok(Response::new(502, "Not a config from lesma.eu".to_string()));
+    }
+    Ok(Response::new(status, body_str.as_str()))
 }
 
-async fn handle_post(req: Request, _: Params) -> anyhow::Result<Response> {
-    let body_bytes = req.body();
-    let body_str = String::from_utf8(body_bytes.to_vec())?;
-    let payload = urlencoding::encode(body_str.as_str());
+async fn post_to_lesma(content: &str, expiry: Expiry) -> anyhow::Result<Response> {
+    let payload = urlencoding::encode(content);
     let body = format!("lesma={}", payload);
-    let url = format!("{}?expire=24", LESMA_BASE_URL);
+    let url = match expiry {
+        Expiry::Hours(hours) => format!("{}?expire={}", LESMA_BASE_URL, hours),
+        Expiry::Never => LESMA_BASE_URL.to_string(),
+    };
     let request = Request::builder()
         .method(Method::Post)
         .header("Content-Type", "application/x-www-form-urlencoded")
@@ -48,12 +391,81 @@ async fn handle_post(req: Request, _: Params) -> anyhow::Result<Response> {
             .strip_prefix('/')
             .ok_or_else(|| anyhow::anyhow!("Failed to parse URL returned from lesma.eu"))?;
 
-        Ok(Response::new(status, id))
+        Ok(Response::new(201, id))
     } else {
         Ok(Response::new(status, "Error from lesma.eu".to_string()))
     }
 }
 
+async fn handle_get(req: Request, params: Params) -> anyhow::Result<Response> {
+    if let Some(retry_after) = check_rate_limit(&req, get_limit("get_limit", DEFAULT_GET_LIMIT))? {
+        return Ok(rate_limited_resp(retry_after));
+    }
+
+    let backend = match get_query(&req).get("backend") {
+        Some(name) => Backend::parse(name),
+        None => default_backend(),
+    };
+    let backend = match backend {
+        Ok(backend) => backend,
+        Err(e) => return Ok(Response::new(400, e.to_string())),
+    };
+
+    let id = params.get("id").unwrap_or_default();
+    if !is_valid_id(id, MAX_LESMA_ID_LENGTH) {
+        return Ok(Response::new(400, "Invalid config ID"));
+    }
+
+    if let Some(content) = get_from_kv(id)? {
+        return Ok(Response::new(200, content));
+    }
+    match backend {
+        // configs stored for editing are there, the UI asks for them with `?backend=lesma`
+        Backend::Lesma => get_from_lesma(id).await,
+        Backend::Kv => Ok(Response::new(404, "Config not found")),
+    }
+}
+
+async fn handle_post(req: Request, _: Params) -> anyhow::Result<Response> {
+    if let Some(retry_after) = check_rate_limit(&req, get_limit("post_limit", DEFAULT_POST_LIMIT))?
+    {
+        return Ok(rate_limited_resp(retry_after));
+    }
+
+    let query = get_query(&req);
+
+    let backend = match query.get("backend") {
+        Some(name) => Backend::parse(name),
+        None => default_backend(),
+    };
+    let expiry = match query.get("expire") {
+        Some(value) => Expiry::parse(value),
+        None => default_expiry(),
+    };
+    let (backend, expiry) = match (backend, expiry) {
+        (Ok(backend), Ok(expiry)) => (backend, expiry),
+        (Err(e), _) | (_, Err(e)) => return Ok(Response::new(400, e.to_string())),
+    };
+
+    let content = match validate_payload(req.body()) {
+        Ok(content) => content,
+        Err(resp) => return Ok(resp),
+    };
+
+    match backend {
+        Backend::Kv => {
+            let id = post_to_kv(content, expiry)?;
+            // not a redirect, fetch in the browser would follow it instead of returning the ID
+            Ok(Response::builder()
+                .status(201)
+                .header("Location", format!("/pbproxy/{}", id))
+                .body(id)
+                .build())
+        }
+        Backend::Lesma => post_to_lesma(&content, expiry).await,
+    }
+}
+
 #[http_component]
 fn handle_weather_data_aggregator_pbproxy(req: Request) -> anyhow::Result<impl IntoResponse> {
     let mut router = Router::default();
@@ -0,0 +1,31 @@
+use spin_cron_sdk::{cron_component, Metadata};
+use spin_sdk::http::{Method, Request, Response};
+
+// the poller runs as an app of its own, so that the API can be deployed without cron
+const POLL_PATH: &str = "/api/v1/poll";
+
+#[cron_component]
+async fn handle_cron_event(metadata: Metadata) -> anyhow::Result<()> {
+    simple_logger::init_with_level(log::Level::Info)?;
+
+    let api_key = spin_sdk::variables::get("poller_api_key")?;
+    if api_key.is_empty() {
+        log::warn!("No API key configured, skipping poll");
+        return Ok(());
+    }
+
+    let api_url = spin_sdk::variables::get("api_url")?;
+    let request = Request::builder()
+        .method(Method::Post)
+        .uri(format!("{}{}", api_url.trim_end_matches('/'), POLL_PATH))
+        .header("authorization", format!("ApiKey {}", api_key))
+        .build();
+    let response: Response = spin_sdk::http::send(request).await?;
+
+    let body = String::from_utf8_lossy(response.body());
+    if *response.status() != 200 {
+        anyhow::bail!("Poll failed with {}: {}", response.status(), body);
+    }
+    log::info!("Polled at {}: {}", metadata.timestamp, body);
+    Ok(())
+}
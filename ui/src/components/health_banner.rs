@@ -0,0 +1,22 @@
+use crate::health::BrokenProvider;
+use leptos::prelude::*;
+
+#[component]
+pub fn HealthBanner(broken_providers: Vec<BrokenProvider>) -> impl IntoView {
+    broken_providers
+        .into_iter()
+        .map(|provider| {
+            let last_success = provider
+                .last_success
+                .map(|t| format!(" Last successful read: {} UTC.", t))
+                .unwrap_or_default();
+            view! {
+                <p style="color: #C00000;">
+                    "Data from " <b>{provider.name}</b>
+                    " cannot be read at the moment, so its stations show N/A. "
+                    "This is not the fault of the stations." <small>{last_success}</small>
+                </p>
+            }
+        })
+        .collect_view()
+}
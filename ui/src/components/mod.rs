@@ -1,4 +1,5 @@
 pub mod config_dialog;
+pub mod health_banner;
 pub mod import_dialog;
 pub mod message_dialog;
 pub mod subtitle_line;
@@ -6,6 +7,7 @@ pub mod title_line;
 pub mod weather_data_table;
 
 pub use config_dialog::ConfigDialog;
+pub use health_banner::HealthBanner;
 pub use import_dialog::ImportDialog;
 pub use message_dialog::MessageDialog;
 pub use subtitle_line::SubtitleLine;
@@ -9,6 +9,17 @@ use url::Url;
 const AEMET_BASE_URL: &str = "https://www.aemet.es/";
 const METEOCAT_BASE_URL: &str = "https://www.meteo.cat/";
 const METEOCLIMATIC_BASE_URL: &str = "https://www.meteoclimatic.net/";
+const HOLFUY_BASE_URL: &str = "https://holfuy.com/";
+const WINDGURU_BASE_URL: &str = "https://www.windguru.cz/";
+const WUNDERGROUND_BASE_URL: &str = "https://www.wunderground.com/";
+const AMBIENT_BASE_URL: &str = "https://ambientweather.net/";
+// providers where the path alone identifies the station
+const STATION_PATH_BASE_URLS: &[&str] = &[
+    HOLFUY_BASE_URL,
+    WINDGURU_BASE_URL,
+    WUNDERGROUND_BASE_URL,
+    AMBIENT_BASE_URL,
+];
 
 const CONFIG_ANNOTATIONS: &str = r#"
 # This is your configuration file. Feel free to edit it. When you are done:
@@ -23,6 +34,10 @@ const CONFIG_ANNOTATIONS: &str = r#"
 #   https://www.meteo.cat/observacions/xema
 #   https://www.aemet.es/en/eltiempo/observacion/ultimosdatos
 #   https://www.openwindmap.org
+#   https://holfuy.com/en/map
+#   https://www.windguru.cz/map/station
+#   https://www.wunderground.com/wundermap
+#   https://ambientweather.net (public dashboards)
 #
 # Also links to www.weatherlink.com work, for instance:
 #   https://www.weatherlink.com/embeddablePage/show/ba1da3b04c2d42f0963afb6cdc9fac77/wide
@@ -34,10 +49,31 @@ const CONFIG_ANNOTATIONS: &str = r#"
 #   wind_direction
 #   wind_speed
 #   gusts_speed
+#   gusts_speed_max
+#   wind_speed_min
 #   humidity
 #   precipitation
 #   temperature
+#   temperature_max
+#   temperature_min
 #   pressure
+#   pressure_sea_level
+#   dew_point
+#   status
+#   solar_radiation
+#   uv_index
+#   latitude
+#   longitude
+#
+# Forecast for the coordinates of the station is available 1, 3 and 6 hours ahead:
+#   forecast_wind_speed_+3h
+#   forecast_gusts_speed_+3h
+#   forecast_temperature_+3h
+#
+# OpenWindMap stations report wind over the last 10, 30 and 60 minutes:
+#   wind_speed_min_30m
+#   wind_speed_avg_30m
+#   wind_speed_max_30m
 #
 "#;
 
@@ -115,17 +151,16 @@ pub fn set_local_config(config: &Config) {
     });
 }
 
-async fn do_upload_config(config: &Config) -> anyhow::Result<String> {
+async fn do_upload_config(config: &Config, url: &str) -> anyhow::Result<String> {
     let config_str = serde_json::to_string_pretty(&config).context("Failed to serialize config")?;
     let config_annotated = CONFIG_ANNOTATIONS.to_string() + config_str.as_str();
 
-    let url = "/pbproxy";
     let req = Request::post(url)
         .body(config_annotated)
         .context("Failed to prepare request")?;
     let resp = req.send().await.context("Failed to get response")?;
 
-    if resp.status() == 303 {
+    if resp.status() == 201 {
         let id = resp.text().await.context("Failed to decode response")?;
         return Ok(id);
     }
@@ -133,15 +168,32 @@ async fn do_upload_config(config: &Config) -> anyhow::Result<String> {
 }
 
 pub async fn upload_config(config: &Config) -> anyhow::Result<String> {
-    do_upload_config(config)
+    do_upload_config(config, "/pbproxy")
         .await
         .context("Failed to upload config")
 }
 
+// Editing happens in the lesma.eu editor, so the config has to be stored there
+pub async fn upload_config_for_editing(config: &Config) -> anyhow::Result<String> {
+    do_upload_config(config, "/pbproxy?backend=lesma")
+        .await
+        .context("Failed to upload config for editing")
+}
+
 pub async fn download_config(id: &str) -> anyhow::Result<Config> {
     let url = format!("/pbproxy/{}", id);
-    let req = Request::get(&url);
-    let resp = req.send().await.context("Failed to get response")?;
+    let mut resp = Request::get(&url)
+        .send()
+        .await
+        .context("Failed to get response")?;
+    // configs stored for editing are kept at lesma.eu
+    if resp.status() == 404 {
+        let url = format!("/pbproxy/{}?backend=lesma", id);
+        resp = Request::get(&url)
+            .send()
+            .await
+            .context("Failed to get response")?;
+    }
 
     if resp.status() == 200 {
         let config = resp.text().await.context("Failed to decode response")?;
@@ -179,6 +231,11 @@ fn sanitize_url(url: &str) -> anyhow::Result<String> {
         sanitize_url_meteoclimatic(url)
     } else if url_lower.starts_with(METEOCAT_BASE_URL) {
         sanitize_url_meteocat(url)
+    } else if STATION_PATH_BASE_URLS
+        .iter()
+        .any(|base_url| url_lower.starts_with(base_url))
+    {
+        sanitize_url_station_path(url)
     } else {
         console_warn(&format!("Unsupported source: {}", url));
         Ok(url.to_string())
@@ -240,3 +297,10 @@ fn sanitize_url_meteocat(url: &str) -> anyhow::Result<String> {
         .unwrap_or(&url_string)
         .to_string())
 }
+
+fn sanitize_url_station_path(url: &str) -> anyhow::Result<String> {
+    let mut url = Url::parse(url)?;
+    url.set_query(None);
+    url.set_fragment(None);
+    Ok(url.to_string())
+}
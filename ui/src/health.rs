@@ -0,0 +1,52 @@
+use crate::session::get_session_token;
+use anyhow::Context;
+use gloo_net::http::Request;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+const STATUS_BROKEN: &str = "broken";
+
+#[derive(Deserialize, Debug)]
+struct ProviderHealthRaw {
+    status: String,
+    last_success: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HealthRaw {
+    providers: BTreeMap<String, ProviderHealthRaw>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BrokenProvider {
+    pub name: String,
+    pub last_success: Option<String>,
+}
+
+pub async fn get_broken_providers() -> anyhow::Result<Vec<BrokenProvider>> {
+    let token = get_session_token().await?;
+    let resp = Request::get("/api/v1/health")
+        .header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .await
+        .context("Failed to get response")?;
+
+    if !resp.ok() {
+        anyhow::bail!("HTTP error from the health API: {}", resp.status());
+    }
+    let health: HealthRaw = resp
+        .json()
+        .await
+        .context("Failed to parse health response JSON")?;
+
+    let broken = health
+        .providers
+        .into_iter()
+        .filter(|(_, provider)| provider.status == STATUS_BROKEN)
+        .map(|(name, provider)| BrokenProvider {
+            name,
+            last_success: provider.last_success,
+        })
+        .collect();
+    Ok(broken)
+}
@@ -1,13 +1,20 @@
 mod components;
 mod config;
+mod health;
+mod session;
 mod utils;
 mod weather;
 
 use anyhow::{Context, anyhow};
 use components::{
-    ConfigDialog, ImportDialog, MessageDialog, SubtitleLine, TitleLine, WeatherDataTable,
+    ConfigDialog, HealthBanner, ImportDialog, MessageDialog, SubtitleLine, TitleLine,
+    WeatherDataTable,
 };
-use config::{Config, download_config, get_local_config, set_local_config, upload_config};
+use config::{
+    Config, download_config, get_local_config, set_local_config, upload_config,
+    upload_config_for_editing,
+};
+use health::get_broken_providers;
 use leptos::ev::MouseEvent;
 use leptos::prelude::*;
 use leptos::reactive::signal::WriteSignal;
@@ -129,6 +136,13 @@ pub fn App(in_preview_mode: bool) -> impl IntoView {
         }
     });
 
+    let broken_providers = LocalResource::new(move || async move {
+        get_broken_providers().await.unwrap_or_else(|e| {
+            log_anyhow_error(e.context("Failed to load provider health"));
+            Vec::new()
+        })
+    });
+
     let (config_dialog_is_open, set_config_dialog_is_open) = signal(false);
     let (import_dialog_is_open, set_import_dialog_is_open) = signal(false);
     let (message_dialog_is_open, set_message_dialog_is_open) = signal(false);
@@ -163,7 +177,7 @@ pub fn App(in_preview_mode: bool) -> impl IntoView {
                 let err_msg = "Failed to edit config";
                 let dialog_msg = "Sorry, we were unable to edit this configuration.".to_owned();
 
-                let id = match upload_config(&config).await {
+                let id = match upload_config_for_editing(&config).await {
                     Ok(id) => id,
                     Err(e) => {
                         return Err((e.context(err_msg), dialog_msg));
@@ -359,6 +373,7 @@ pub fn App(in_preview_mode: bool) -> impl IntoView {
                 </header>
 
                 <main class="container">
+                    <HealthBanner broken_providers=broken_providers.read().clone().unwrap_or_default() />
                     <WeatherDataTable weather_data=weather_data />
                 </main>
             }
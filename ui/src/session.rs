@@ -0,0 +1,49 @@
+use anyhow::Context;
+use gloo_net::http::Request;
+use gloo_storage::{SessionStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+const SESSION_KEY: &str = "session";
+
+// renew the token a bit before it expires to account for clock skew
+const SESSION_RENEW_MARGIN_SECS: i64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Session {
+    token: String,
+    expires_at: i64,
+}
+
+async fn request_session() -> anyhow::Result<Session> {
+    let resp = Request::post("/api/v1/session")
+        .send()
+        .await
+        .context("Failed to get response")?;
+
+    if !resp.ok() {
+        anyhow::bail!(
+            "Unexpected response from the session API: {}",
+            resp.status()
+        );
+    }
+    resp.json::<Session>()
+        .await
+        .context("Failed to parse session response JSON")
+}
+
+/// Returns a session token for the weather-data API, requesting a new one when needed.
+pub async fn get_session_token() -> anyhow::Result<String> {
+    let now = chrono::Utc::now().timestamp();
+    let cached = SessionStorage::get::<Session>(SESSION_KEY)
+        .ok()
+        .filter(|session| session.expires_at - SESSION_RENEW_MARGIN_SECS > now);
+    if let Some(session) = cached {
+        return Ok(session.token);
+    }
+
+    let session = request_session()
+        .await
+        .context("Failed to obtain session")?;
+    SessionStorage::set(SESSION_KEY, &session).context("Failed to save session")?;
+    Ok(session.token)
+}
@@ -1,13 +1,13 @@
 use crate::config::Config;
+use crate::session::get_session_token;
 use crate::utils::log_anyhow_error;
 use anyhow::Context;
 use chrono::NaiveDateTime;
 use gloo_net::http::Request;
 use serde::Deserialize;
+use serde_json::json;
 use std::collections::HashMap;
 
-const API_TOKEN: &str = env!("SPIN_VARIABLE_API_TOKEN");
-
 const KEY_UPDATE_TIME: &str = "update_time";
 const KEY_UPDATE_TIME_UTC: &str = "update_time_utc";
 const KEY_UPDATE_TIME_AGO: &str = "update_time_ago";
@@ -45,6 +45,7 @@ fn time_delta(now: NaiveDateTime, then: NaiveDateTime) -> String {
     }
 }
 
+#[allow(clippy::collapsible_if)]
 fn convert_utc_to_ago(units: &mut HashMap<String, String>, weather_data: &mut WeatherDataRaw) {
     if let Some(update_time_unit) = units.get(KEY_UPDATE_TIME).cloned() {
         if update_time_unit == "UTC" {
@@ -74,16 +75,33 @@ fn convert_utc_to_ago(units: &mut HashMap<String, String>, weather_data: &mut We
 }
 
 pub async fn get_weather_data(config: Config) -> anyhow::Result<WeatherData> {
-    let api_url = format!("/api/v1?token={}", API_TOKEN);
+    let api_url = "/api/v1";
+    let token = get_session_token().await?;
     let sources = config
         .stations
         .iter()
         .map(|item| item.url.clone())
         .collect::<Vec<_>>();
-    let sources = serde_json::to_string(&sources)
+    // only ask for the keys that are shown, so that the API can skip the rest
+    let mut fields = config
+        .measurements
+        .iter()
+        .filter_map(|m| match m.key.as_str() {
+            KEY_LOCATION => None,
+            KEY_UPDATE_TIME_UTC | KEY_UPDATE_TIME_AGO => Some(KEY_UPDATE_TIME.to_owned()),
+            key => Some(key.to_owned()),
+        })
+        .collect::<Vec<_>>();
+    fields.sort();
+    fields.dedup();
+    let sources = serde_json::to_string(&json!({"stations": sources, "fields": fields}))
         .context("Failed to serialize sources data")?
         .to_string();
-    let resp = Request::post(&api_url).body(&sources)?.send().await?;
+    let resp = Request::post(api_url)
+        .header("Authorization", &format!("Bearer {}", token))
+        .body(&sources)?
+        .send()
+        .await?;
 
     let text = resp.text().await?;
     if !resp.ok() {
@@ -0,0 +1,59 @@
+use serde::Deserialize;
+use serde_json::Value;
+use spin_sdk::http::{IntoResponse, Method, Request, Response};
+use spin_sdk::http_component;
+
+/// Webhook to call and the payload to post to it, as sent by the API
+#[derive(Deserialize, Debug)]
+struct Delivery {
+    webhook: String,
+    payload: Value,
+}
+
+/// Only HTTPS, or plain HTTP to the local host, same as the rules accepted by the API
+fn is_allowed(webhook: &url::Url) -> bool {
+    let local = matches!(webhook.host_str(), Some("127.0.0.1") | Some("localhost"));
+    match webhook.scheme() {
+        "https" => true,
+        "http" => local,
+        _ => false,
+    }
+}
+
+async fn deliver(delivery: Delivery) -> anyhow::Result<Response> {
+    let webhook = match url::Url::parse(&delivery.webhook) {
+        Ok(webhook) if is_allowed(&webhook) => webhook,
+        _ => return Ok(Response::new(400, "Invalid webhook")),
+    };
+
+    let request = Request::builder()
+        .method(Method::Post)
+        .uri(webhook.as_str())
+        .header("content-type", "application/json")
+        .body(delivery.payload.to_string())
+        .build();
+
+    let response: Response = match spin_sdk::http::send(request).await {
+        Ok(response) => response,
+        Err(e) => return Ok(Response::new(502, format!("Webhook not reachable: {}", e))),
+    };
+    if !(200..300).contains(response.status()) {
+        let message = format!("Unexpected response from webhook: {}", response.status());
+        return Ok(Response::new(502, message));
+    }
+    Ok(Response::new(204, ()))
+}
+
+/// Posts alert payloads to the webhooks of the rules.
+///
+/// Kept apart from the API so that only this component may reach arbitrary hosts.
+#[http_component]
+async fn handle_webhooks(req: Request) -> anyhow::Result<impl IntoResponse> {
+    if *req.method() != Method::Post {
+        return Ok(Response::new(405, ()));
+    }
+    match serde_json::from_slice::<Delivery>(req.body()) {
+        Ok(delivery) => deliver(delivery).await,
+        Err(e) => Ok(Response::new(400, format!("Invalid delivery: {}", e))),
+    }
+}